@@ -0,0 +1,727 @@
+//! Discovery of the addons (`CfgPatches` classes) provided and required by PBOs.
+
+use crate::{
+    arma_config::{Cfg, Value},
+    pbo::Pbo,
+    rap,
+    util::list_mods,
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use walkdir::WalkDir;
+
+/// The `CfgPatches` of a single PBO.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Addon {
+    /// The file name of the PBO.
+    pub pbo: String,
+    /// The names of the `CfgPatches` classes.
+    pub patches: BTreeSet<String>,
+    /// The `requiredAddons` of all `CfgPatches` classes.
+    pub required: BTreeSet<String>,
+}
+
+impl Addon {
+    /// Read the `config.bin` or `config.cpp` of a PBO.
+    ///
+    /// If the PBO has no config or it cannot be parsed, the name of the PBO is used as the only
+    /// patch since that is the name most addons use.
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let pbo_name = path
+            .file_name()
+            .context("Path has no filename")?
+            .to_string_lossy()
+            .into_owned();
+
+        let mut addon = Self {
+            pbo: pbo_name,
+            ..Self::default()
+        };
+
+        match read_config(path) {
+            Ok(Some(config)) => {
+                if let Some(patches) = config.root().get("CfgPatches").and_then(Value::as_class) {
+                    for (name, patch) in patches {
+                        addon.patches.insert(name.clone());
+                        addon.required.extend(
+                            patch
+                                .get("requiredAddons")
+                                .and_then(Value::as_list)
+                                .unwrap_or_default()
+                                .iter()
+                                .filter_map(Value::as_str)
+                                .map(str::to_owned),
+                        );
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(err) => debug!("Could not read config of {}: {:?}", path.display(), err),
+        }
+
+        if addon.patches.is_empty() {
+            if let Some(stem) = path.file_stem() {
+                addon.patches.insert(stem.to_string_lossy().into_owned());
+            }
+        }
+
+        Ok(addon)
+    }
+}
+
+fn read_config(path: &Path) -> Result<Option<Cfg>> {
+    let mut pbo = Pbo::open(path)?;
+
+    if pbo.entry("config.bin").is_some() {
+        let data = pbo.read_file("config.bin")?;
+        return rap::from_bytes(&data).map(Some);
+    }
+
+    if pbo.entry("config.cpp").is_some() {
+        let data = pbo.read_file("config.cpp")?;
+        if rap::is_rapified(&data) {
+            return rap::from_bytes(&data).map(Some);
+        }
+        return Cfg::from_string(&String::from_utf8_lossy(&data)).map(Some);
+    }
+
+    Ok(None)
+}
+
+/// Find all PBOs in the `addons` directories below `path`, skipping directories in `exclude`.
+///
+/// The encrypted `.ebo` files of creator DLCs are included. Their configs can not be read, so
+/// they are indexed by their file name.
+pub fn find_pbos<P>(path: P, exclude: &[PathBuf]) -> Vec<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let mut pbos = WalkDir::new(path)
+        .into_iter()
+        .filter_entry(|entry| !exclude.iter().any(|e| entry.path() == e))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && is_addon_pbo(entry.path()))
+        .map(|entry| entry.into_path())
+        .collect::<Vec<PathBuf>>();
+
+    pbos.sort();
+    pbos
+}
+
+fn is_addon_pbo(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(|ext| ext.eq_ignore_ascii_case("pbo") || ext.eq_ignore_ascii_case("ebo"))
+        .unwrap_or(false)
+        && path
+            .parent()
+            .and_then(Path::file_name)
+            .map(|dir| dir.to_string_lossy().eq_ignore_ascii_case("addons"))
+            .unwrap_or(false)
+}
+
+/// Directories in the server directory that contain addons which are not part of the game.
+const NOT_BASE_GAME: &[&str] = &["mods", "mpmissions", "profiles", "steamapps"];
+
+/// Find the PBOs of the base game.
+///
+/// The game loads the `addons` directory of the server and the `addons` directories of the DLCs
+/// directly below it. Hidden directories, mods in `@Mod` directories, missions, profiles and
+/// downloaded workshop items are skipped.
+fn base_game_pbos(server_path: &Path) -> Vec<PathBuf> {
+    let is_base_game = |name: &str| {
+        !name.starts_with('.')
+            && !name.starts_with('@')
+            && !NOT_BASE_GAME.iter().any(|dir| {
+                name.get(..dir.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(dir))
+            })
+    };
+
+    let mut pbos = WalkDir::new(server_path)
+        .max_depth(3)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() != 1
+                || !entry.file_type().is_dir()
+                || is_base_game(&entry.file_name().to_string_lossy())
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && is_addon_pbo(entry.path()))
+        .map(|entry| entry.into_path())
+        .collect::<Vec<PathBuf>>();
+
+    pbos.sort();
+    pbos
+}
+
+/// Read the addons of all PBOs below `path`.
+pub fn scan<P>(path: P, exclude: &[PathBuf]) -> Result<Vec<Addon>>
+where
+    P: AsRef<Path>,
+{
+    find_pbos(path, exclude).iter().map(Addon::read).collect()
+}
+
+/// The number of PBOs in a directory and when the newest of them was modified.
+///
+/// Used to notice that the base game or a mod changed since it was indexed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub pbos: usize,
+    /// Milliseconds since the epoch.
+    pub modified: u64,
+}
+
+impl Stamp {
+    fn of(pbos: &[PathBuf]) -> Self {
+        let modified = pbos
+            .iter()
+            .filter_map(|pbo| fs::metadata(pbo).and_then(|m| m.modified()).ok())
+            .filter_map(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| time.as_millis() as u64)
+            .max()
+            .unwrap_or(0);
+
+        Self {
+            pbos: pbos.len(),
+            modified,
+        }
+    }
+}
+
+/// Get the names of the addons a mission requires from its `mission.sqm`.
+pub fn mission_requirements(mission_sqm: &Cfg) -> BTreeSet<String> {
+    mission_sqm
+        .root()
+        .get("addons")
+        .and_then(Value::as_list)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Value::as_str)
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Get the addons in `required` that are not in `provided`. Names are compared
+/// case-insensitively.
+pub fn missing<'a, I>(required: &'a BTreeSet<String>, provided: I) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a Addon>,
+{
+    let provided = provided
+        .into_iter()
+        .flat_map(|addon| addon.patches.iter())
+        .map(|patch| patch.to_lowercase())
+        .collect::<BTreeSet<String>>();
+
+    required
+        .iter()
+        .filter(|addon| !provided.contains(&addon.to_lowercase()))
+        .map(String::as_str)
+        .collect()
+}
+
+/// File name of the addon index inside the mods directory.
+pub static INDEX_FILE: &str = ".addons.json";
+
+/// Where an addon comes from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Provider<'a> {
+    BaseGame,
+    Mod(&'a str),
+}
+
+/// An addon required by a mod of a modpack that neither the base game nor the modpack provide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingAddon {
+    pub required_by: String,
+    pub addon: String,
+    /// Installed mods outside of the modpack that provide the addon.
+    pub providers: Vec<String>,
+}
+
+impl fmt::Display for MissingAddon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} requires {}", self.required_by, self.addon)?;
+
+        if self.providers.is_empty() {
+            write!(f, " which no installed mod provides")
+        } else {
+            write!(f, " provided by {}", self.providers.join(", "))
+        }
+    }
+}
+
+/// The addons of the base game and every installed mod.
+///
+/// Reading the configs of all PBOs takes a while, so the index is stored in the mods directory
+/// and only the entry of a mod is refreshed when it is installed or updated. Entries whose
+/// [`Stamp`] changed, e.g. after a game update, are refreshed by [`AddonIndex::update_stale`].
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AddonIndex {
+    pub base_game: Vec<Addon>,
+    #[serde(default)]
+    pub base_game_stamp: Stamp,
+    /// The addons of each mod by the name of its directory.
+    pub mods: BTreeMap<String, Vec<Addon>>,
+    /// The stamps of the mods when they were indexed.
+    #[serde(default)]
+    pub stamps: BTreeMap<String, Stamp>,
+}
+
+impl AddonIndex {
+    /// Scan the base game and all mods below `server_path`.
+    pub fn build<P>(server_path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let server_path = server_path.as_ref();
+        let mods_path = server_path.join("mods");
+
+        let pbos = base_game_pbos(server_path);
+        let mut index = Self {
+            base_game: pbos
+                .iter()
+                .map(Addon::read)
+                .collect::<Result<_>>()
+                .context("Could not read addons of the base game")?,
+            base_game_stamp: Stamp::of(&pbos),
+            ..Self::default()
+        };
+
+        if mods_path.is_dir() {
+            for name in list_mods(&mods_path)? {
+                index.update_mod(server_path, &name)?;
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Load the stored index of the server at `server_path`.
+    pub fn load<P>(server_path: P) -> Result<Option<Self>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = server_path.as_ref().join("mods").join(INDEX_FILE);
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path).context("Could not read addon index")?;
+        Ok(Some(
+            serde_json::from_str(&content).context("Could not parse addon index")?,
+        ))
+    }
+
+    /// Load the stored index or build it if there is none or `refresh` is set.
+    ///
+    /// A newly built index is stored if the mods directory is writable.
+    pub fn load_or_build<P>(server_path: P, refresh: bool) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let server_path = server_path.as_ref();
+
+        if !refresh {
+            if let Some(index) = Self::load(server_path)? {
+                return Ok(index);
+            }
+        }
+
+        let index = Self::build(server_path)?;
+        if let Err(err) = index.save(server_path) {
+            debug!("Could not store addon index: {:?}", err);
+        }
+
+        Ok(index)
+    }
+
+    pub fn save<P>(&self, server_path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = server_path.as_ref().join("mods").join(INDEX_FILE);
+        let content = serde_json::to_string(self).context("Could not serialize addon index")?;
+
+        fs::write(&path, content)
+            .with_context(|| format!("Could not write addon index {}", path.display()))
+    }
+
+    /// Rescan a single mod, removing it from the index if it is no longer installed.
+    pub fn update_mod<P>(&mut self, server_path: P, name: &str) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mod_path = server_path.as_ref().join("mods").join(name);
+
+        if !mod_path.is_dir() {
+            self.mods.remove(name);
+            self.stamps.remove(name);
+            return Ok(());
+        }
+
+        let pbos = find_pbos(&mod_path, &[]);
+        let addons = pbos
+            .iter()
+            .map(Addon::read)
+            .collect::<Result<_>>()
+            .with_context(|| format!("Could not read addons of {}", name))?;
+        self.mods.insert(name.to_owned(), addons);
+        self.stamps.insert(name.to_owned(), Stamp::of(&pbos));
+
+        Ok(())
+    }
+
+    /// Rescan the base game and the mods in `names` if they changed since they were indexed or
+    /// are not in the index yet. The index is stored if anything was rescanned.
+    pub fn update_stale<P>(&mut self, server_path: P, names: &[&str]) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let server_path = server_path.as_ref();
+        let mut changed = false;
+
+        let pbos = base_game_pbos(server_path);
+        let stamp = Stamp::of(&pbos);
+        if stamp != self.base_game_stamp {
+            debug!("Base game changed since it was indexed");
+
+            self.base_game = pbos
+                .iter()
+                .map(Addon::read)
+                .collect::<Result<_>>()
+                .context("Could not read addons of the base game")?;
+            self.base_game_stamp = stamp;
+            changed = true;
+        }
+
+        for &name in names {
+            let stamp = Stamp::of(&find_pbos(server_path.join("mods").join(name), &[]));
+
+            if self.stamps.get(name) != Some(&stamp) {
+                debug!("Mod {} changed since it was indexed", name);
+
+                self.update_mod(server_path, name)?;
+                changed = true;
+            }
+        }
+
+        if changed {
+            if let Err(err) = self.save(server_path) {
+                debug!("Could not store addon index: {:?}", err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Update the entry of a mod in the stored index, building the index if there is none yet.
+    pub fn refresh_mod<P>(server_path: P, name: &str) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let server_path = server_path.as_ref();
+
+        let index = match Self::load(server_path)? {
+            Some(mut index) => {
+                index.update_mod(server_path, name)?;
+                index
+            }
+            None => Self::build(server_path)?,
+        };
+
+        index.save(server_path)
+    }
+
+    /// Get the base game or mods that provide `addon`. Names are compared case-insensitively.
+    pub fn providers(&self, addon: &str) -> Vec<Provider<'_>> {
+        let provides = |addons: &[Addon]| {
+            addons
+                .iter()
+                .flat_map(|a| a.patches.iter())
+                .any(|patch| patch.eq_ignore_ascii_case(addon))
+        };
+
+        let mut providers = Vec::new();
+
+        if provides(&self.base_game) {
+            providers.push(Provider::BaseGame);
+        }
+
+        providers.extend(
+            self.mods
+                .iter()
+                .filter(|(_, addons)| provides(addons))
+                .map(|(name, _)| Provider::Mod(name)),
+        );
+
+        providers
+    }
+
+    /// Get the addons a mod requires that it does not provide itself.
+    pub fn requirements(&self, name: &str) -> Option<BTreeSet<String>> {
+        let addons = self.mods.get(name)?;
+        let required = addons
+            .iter()
+            .flat_map(|a| a.required.iter().cloned())
+            .collect::<BTreeSet<String>>();
+
+        Some(
+            missing(&required, addons)
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+        )
+    }
+
+    /// Get the addons required by `mods` that are neither provided by the base game nor by one
+    /// of `mods`.
+    pub fn missing_dependencies(&self, mods: &[&str]) -> Vec<MissingAddon> {
+        let mut missing = Vec::new();
+
+        for &name in mods {
+            for addon in self.requirements(name).unwrap_or_default() {
+                let providers = self.providers(&addon);
+
+                let provided = providers.iter().any(|provider| match provider {
+                    Provider::BaseGame => true,
+                    Provider::Mod(m) => mods.contains(m),
+                });
+
+                if !provided {
+                    missing.push(MissingAddon {
+                        required_by: name.to_owned(),
+                        providers: providers
+                            .into_iter()
+                            .filter_map(|provider| match provider {
+                                Provider::BaseGame => None,
+                                Provider::Mod(m) => Some(m.to_owned()),
+                            })
+                            .collect(),
+                        addon,
+                    });
+                }
+            }
+        }
+
+        missing
+    }
+
+    /// Order `mods` so every mod comes after the mods it requires addons from.
+    ///
+    /// Mods keep their relative order where they do not depend on each other. Mods that depend
+    /// on each other in a cycle are appended in their original order.
+    pub fn load_order<'a>(&self, mods: &[&'a str]) -> Vec<&'a str> {
+        // dependencies[i] are the indices of the mods that mods[i] requires
+        let mut dependencies = mods
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                self.requirements(name)
+                    .unwrap_or_default()
+                    .iter()
+                    .flat_map(|addon| self.providers(addon))
+                    .filter_map(|provider| match provider {
+                        Provider::BaseGame => None,
+                        Provider::Mod(m) => mods.iter().position(|&n| n == m),
+                    })
+                    .filter(|&j| j != i)
+                    .collect::<BTreeSet<usize>>()
+            })
+            .collect::<Vec<BTreeSet<usize>>>();
+
+        let mut order = Vec::with_capacity(mods.len());
+        let mut done = vec![false; mods.len()];
+
+        while let Some(i) = (0..mods.len()).find(|&i| !done[i] && dependencies[i].is_empty()) {
+            done[i] = true;
+            order.push(mods[i]);
+
+            for deps in &mut dependencies {
+                deps.remove(&i);
+            }
+        }
+
+        if order.len() < mods.len() {
+            debug!("Mods with cyclic dependencies are loaded in config order");
+            order.extend((0..mods.len()).filter(|&i| !done[i]).map(|i| mods[i]));
+        }
+
+        order
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pbo::PboWriter;
+
+    fn write_pbo(path: &Path, config: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        PboWriter::new()
+            .add_file("config.cpp", 0, config.as_bytes().to_vec())
+            .write_file(path)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = dir.path();
+
+        write_pbo(
+            &server.join("addons/data_f.pbo"),
+            "class CfgPatches { class A3_Data_F { requiredAddons[] = {}; }; };",
+        );
+        write_pbo(
+            &server.join("mods/cba/addons/cba_main.pbo"),
+            r#"class CfgPatches { class cba_main { requiredAddons[] = {"A3_Data_F"}; }; };"#,
+        );
+        write_pbo(
+            &server.join("mods/ace/addons/ace_main.pbo"),
+            r#"class CfgPatches { class ace_main { requiredAddons[] = {"cba_main", "a3_data_f"}; }; };"#,
+        );
+        write_pbo(
+            &server.join("mods/ace/addons/ace_medical.pbo"),
+            r#"class CfgPatches { class ace_medical { requiredAddons[] = {"ace_main", "acex_main"}; }; };"#,
+        );
+
+        // addons outside of the game directories are not part of the base game
+        for path in &[
+            "steamapps/workshop/content/107410/450814997/addons/cba_main.pbo",
+            "@CBA_A3/addons/cba_main.pbo",
+            "profiles/public/addons/cba_main.pbo",
+        ] {
+            write_pbo(
+                &server.join(path),
+                "class CfgPatches { class cba_main {}; };",
+            );
+        }
+
+        let index = AddonIndex::load_or_build(server, false).unwrap();
+        assert_eq!(AddonIndex::load(server).unwrap().as_ref(), Some(&index));
+
+        assert_eq!(index.providers("CBA_MAIN"), vec![Provider::Mod("cba")]);
+        assert_eq!(index.providers("a3_data_f"), vec![Provider::BaseGame]);
+        assert!(index.providers("acex_main").is_empty());
+
+        let requirements = index.requirements("ace").unwrap();
+        assert_eq!(
+            requirements.into_iter().collect::<Vec<_>>(),
+            vec!["a3_data_f", "acex_main", "cba_main"]
+        );
+        assert!(index.requirements("acex").is_none());
+    }
+
+    #[test]
+    fn test_update_mod() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = dir.path();
+        fs::create_dir(server.join("mods")).unwrap();
+
+        let mut index = AddonIndex::build(server).unwrap();
+        assert!(index.mods.is_empty());
+
+        write_pbo(
+            &server.join("mods/cba/addons/cba_main.pbo"),
+            "class CfgPatches { class cba_main {}; };",
+        );
+        index.update_mod(server, "cba").unwrap();
+        assert_eq!(index.providers("cba_main"), vec![Provider::Mod("cba")]);
+
+        fs::remove_dir_all(server.join("mods/cba")).unwrap();
+        index.update_mod(server, "cba").unwrap();
+        assert!(index.mods.is_empty());
+    }
+
+    #[test]
+    fn test_update_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = dir.path();
+
+        write_pbo(
+            &server.join("mods/cba/addons/cba_main.pbo"),
+            "class CfgPatches { class cba_main {}; };",
+        );
+
+        let mut index = AddonIndex::load_or_build(server, false).unwrap();
+        assert!(index.providers("gm_core").is_empty());
+
+        // a creator DLC is added by a game update
+        write_pbo(
+            &server.join("gm/addons/gm_core.ebo"),
+            "class CfgPatches { class gm_core {}; };",
+        );
+        write_pbo(
+            &server.join("mods/cba/addons/cba_common.pbo"),
+            "class CfgPatches { class cba_common {}; };",
+        );
+
+        index.update_stale(server, &["cba"]).unwrap();
+        assert_eq!(index.providers("gm_core"), vec![Provider::BaseGame]);
+        assert_eq!(index.providers("cba_common"), vec![Provider::Mod("cba")]);
+        assert_eq!(AddonIndex::load(server).unwrap().as_ref(), Some(&index));
+    }
+
+    fn addon(patch: &str, required: &[&str]) -> Vec<Addon> {
+        vec![Addon {
+            pbo: format!("{}.pbo", patch),
+            patches: vec![patch.to_owned()].into_iter().collect(),
+            required: required.iter().map(|&r| r.to_owned()).collect(),
+        }]
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let mut index = AddonIndex {
+            base_game: addon("A3_Data_F", &[]),
+            ..AddonIndex::default()
+        };
+        index
+            .mods
+            .insert("ace".into(), addon("ace_main", &["cba_main", "A3_Data_F"]));
+        index.mods.insert("cba".into(), addon("cba_main", &[]));
+        index
+            .mods
+            .insert("acex".into(), addon("acex_main", &["ace_main"]));
+        index.mods.insert("tfar".into(), addon("tfar_core", &[]));
+
+        assert_eq!(
+            index.load_order(&["acex", "tfar", "ace", "cba"]),
+            vec!["tfar", "cba", "ace", "acex"]
+        );
+        assert!(index
+            .missing_dependencies(&["acex", "ace", "cba"])
+            .is_empty());
+
+        assert_eq!(
+            index.missing_dependencies(&["tfar", "ace"]),
+            vec![MissingAddon {
+                required_by: "ace".into(),
+                addon: "cba_main".into(),
+                providers: vec!["cba".into()],
+            }]
+        );
+
+        index
+            .mods
+            .insert("cba".into(), addon("cba_main", &["acex_main"]));
+        assert_eq!(
+            index.load_order(&["tfar", "acex", "ace", "cba"]),
+            vec!["tfar", "acex", "ace", "cba"]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::{bail, ensure, Context, Result};
+use flate2::read::GzDecoder;
+use std::{
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+};
+use thiserror::Error;
+use walkdir::WalkDir;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("Could not open archive")]
+    Open,
+
+    #[error("Could not read archive")]
+    Read,
+
+    #[error("Could not extract archive entry")]
+    Extract,
+
+    #[error("Archive entry escapes the target directory: {0}")]
+    UnsafePath(String),
+}
+
+/// The archive formats mods are distributed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+    SevenZip,
+}
+
+impl ArchiveKind {
+    /// Guess the archive format from the file name.
+    pub fn from_path<P>(path: P) -> Option<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let name = path.as_ref().file_name()?.to_str()?.to_lowercase();
+
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".7z") {
+            Some(Self::SevenZip)
+        } else {
+            None
+        }
+    }
+
+    /// Get the file name of `path` without the archive extension.
+    pub fn file_stem<'a>(&self, path: &'a Path) -> Option<&'a str> {
+        let name = path.file_name()?.to_str()?;
+        let extension = match self {
+            Self::Zip => ".zip",
+            Self::TarGz if name.to_lowercase().ends_with(".tgz") => ".tgz",
+            Self::TarGz => ".tar.gz",
+            Self::SevenZip => ".7z",
+        };
+
+        Some(&name[..name.len() - extension.len()])
+    }
+}
+
+/// Extract the archive at `path` into the directory `target`.
+///
+/// Entries that would end up outside of `target` are rejected.
+pub fn extract<P, Q>(path: P, target: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let target = target.as_ref();
+    let kind = ArchiveKind::from_path(path).context("Unsupported archive format")?;
+
+    fs::create_dir_all(target).context("Could not create target directory")?;
+
+    debug!("Extracting {} to {}", path.display(), target.display());
+
+    match kind {
+        ArchiveKind::Zip => extract_zip(path, target),
+        ArchiveKind::TarGz => extract_tar_gz(path, target),
+        ArchiveKind::SevenZip => extract_7z(path, target),
+    }
+}
+
+fn extract_zip(path: &Path, target: &Path) -> Result<()> {
+    let file = File::open(path).context(ArchiveError::Open)?;
+    let mut archive = zip::ZipArchive::new(file).context(ArchiveError::Read)?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).context(ArchiveError::Read)?;
+        let name = entry
+            .enclosed_name()
+            .map(Path::to_owned)
+            .ok_or_else(|| ArchiveError::UnsafePath(entry.name().to_owned()))?;
+        let entry_path = target.join(name);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&entry_path).context(ArchiveError::Extract)?;
+        } else {
+            if let Some(parent) = entry_path.parent() {
+                fs::create_dir_all(parent).context(ArchiveError::Extract)?;
+            }
+            let mut file = File::create(&entry_path).context(ArchiveError::Extract)?;
+            io::copy(&mut entry, &mut file).context(ArchiveError::Extract)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_tar_gz(path: &Path, target: &Path) -> Result<()> {
+    let file = File::open(path).context(ArchiveError::Open)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+
+    // `unpack` already refuses entries containing `..` or absolute paths
+    archive.unpack(target).context(ArchiveError::Extract)
+}
+
+fn extract_7z(path: &Path, target: &Path) -> Result<()> {
+    sevenz_rust::decompress_file_with_extract_fn(path, target, |entry, reader, dest| {
+        let safe = Path::new(entry.name())
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+
+        if !safe {
+            return Err(sevenz_rust::Error::other(
+                ArchiveError::UnsafePath(entry.name().to_owned()).to_string(),
+            ));
+        }
+
+        sevenz_rust::default_entry_extract_fn(entry, reader, dest)
+    })
+    .context(ArchiveError::Extract)
+}
+
+/// Find the root directory of a mod inside an extracted archive.
+///
+/// The root is the directory named `@ModName`. If there is none, the directory containing the
+/// `addons` folder is used instead.
+pub fn find_mod_root<P>(path: P) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let directories = WalkDir::new(path)
+        .max_depth(3)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir())
+        .collect::<Vec<_>>();
+
+    let roots = directories
+        .iter()
+        .filter(|entry| entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('@'))
+        .map(|entry| entry.path().to_owned())
+        .collect::<Vec<_>>();
+
+    match roots.len() {
+        0 => {}
+        1 => return Ok(roots[0].clone()),
+        _ => bail!(
+            "Archive contains more than one mod:\n{}",
+            roots
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+
+    let root = directories
+        .iter()
+        .find(|entry| entry.file_name().to_string_lossy().to_lowercase() == "addons")
+        .and_then(|entry| entry.path().parent())
+        .context("Could not find a mod directory (`@ModName` or `addons`) in archive")?;
+
+    ensure!(
+        root.starts_with(path),
+        "Mod directory is outside of the archive"
+    );
+
+    Ok(root.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_archive_kind() {
+        assert_eq!(ArchiveKind::from_path("@ACE.zip"), Some(ArchiveKind::Zip));
+        assert_eq!(
+            ArchiveKind::from_path("cba.TAR.GZ"),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            ArchiveKind::from_path("cba.7z"),
+            Some(ArchiveKind::SevenZip)
+        );
+        assert_eq!(ArchiveKind::from_path("@ACE"), None);
+        assert_eq!(
+            ArchiveKind::TarGz.file_stem(Path::new("/tmp/@cba.tar.gz")),
+            Some("@cba")
+        );
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let dir = tempdir().unwrap();
+        let archive_path = dir.path().join("mod.zip");
+
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        let options = zip::write::FileOptions::default();
+        writer
+            .add_directory("release/@Mod/addons/", options)
+            .unwrap();
+        writer
+            .start_file("release/@Mod/addons/mod.pbo", options)
+            .unwrap();
+        writer.write_all(b"pbo").unwrap();
+        writer.finish().unwrap();
+
+        let target = dir.path().join("staging");
+        extract(&archive_path, &target).unwrap();
+
+        assert_eq!(
+            find_mod_root(&target).unwrap(),
+            target.join("release").join("@Mod")
+        );
+        assert!(target.join("release/@Mod/addons/mod.pbo").is_file());
+    }
+
+    #[test]
+    fn test_find_mod_root_without_at() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("cba_a3/Addons")).unwrap();
+
+        assert_eq!(
+            find_mod_root(dir.path()).unwrap(),
+            dir.path().join("cba_a3")
+        );
+    }
+}
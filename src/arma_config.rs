@@ -1,16 +1,20 @@
-use anyhow::Result;
+//! Parser for the text format of Arma configs like `server.cfg`, `config.cpp` or an unbinarized
+//! `mission.sqm`.
+//!
+//! Preprocessor directives are skipped and class inheritance is not resolved, a derived class
+//! only contains its own entries.
+
+use anyhow::{anyhow, Result};
 use nom::{
     branch::alt,
-    bytes::complete::{is_a, tag, tag_no_case, take_till, take_while},
-    character::complete::{alphanumeric1, char, digit1, line_ending, not_line_ending, space0},
-    combinator::{all_consuming, map, map_res, opt},
+    bytes::complete::{is_not, tag, tag_no_case, take_until, take_while1},
+    character::complete::{char, multispace1, not_line_ending},
+    combinator::{all_consuming, map, map_res, opt, peek, value as constant, verify},
     multi::{fold_many0, many0, separated_list},
-    number::complete::double,
-    sequence::{separated_pair, terminated, tuple},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 use std::collections::HashMap;
-use std::str::FromStr;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
@@ -22,118 +26,254 @@ pub enum Value {
     Class(HashMap<String, Value>),
 }
 
+impl Value {
+    /// Get an entry of a class. Like in Arma the key is case-insensitive.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Class(map) => map.get(key).or_else(|| {
+                map.iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                    .map(|(_, v)| v)
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get a nested entry by following the `/` separated path, e.g. `Mission/Intel/briefingName`.
+    pub fn get_path(&self, path: &str) -> Option<&Value> {
+        path.split('/').try_fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Self::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_class(&self) -> Option<&HashMap<String, Value>> {
+        match self {
+            Self::Class(map) => Some(map),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
-pub struct Cfg(Value);
+pub struct Cfg(pub Value);
 
-fn string(i: &str) -> IResult<&str, Value> {
-    let (i, _) = char('"')(i)?;
-    map(take_till(|c| c == '"'), |x: &str| {
-        Value::String(x.to_owned())
-    })(i)
+#[derive(Clone)]
+enum Item<'a> {
+    Entry(&'a str, Value),
+    Ignored,
 }
 
-fn number(i: &str) -> IResult<&str, Value> {
-    map(
-        map_res(tuple((opt(char('-')), digit1)), |(sign, s)| {
-            i64::from_str_radix(&format!("{}{}", sign.unwrap_or('+'), s), 10)
-        }),
-        Value::Number,
-    )(i)
+fn block_comment(i: &str) -> IResult<&str, &str> {
+    delimited(tag("/*"), take_until("*/"), tag("*/"))(i)
 }
 
-fn float(i: &str) -> IResult<&str, Value> {
-    map(double, Value::Float)(i)
+fn line_comment(i: &str) -> IResult<&str, &str> {
+    preceded(tag("//"), not_line_ending)(i)
 }
 
-fn boolean(i: &str) -> IResult<&str, Value> {
-    map(
-        map_res(alt((tag_no_case("true"), tag_no_case("false"))), |t| {
-            bool::from_str(t)
-        }),
-        Value::Boolean,
+fn directive(i: &str) -> IResult<&str, &str> {
+    preceded(char('#'), not_line_ending)(i)
+}
+
+/// Whitespace, comments and preprocessor directives.
+fn ws(i: &str) -> IResult<&str, ()> {
+    constant(
+        (),
+        many0(alt((multispace1, line_comment, block_comment, directive))),
     )(i)
 }
 
-fn list_separator(i: &str) -> IResult<&str, ()> {
-    let (i, _) = space0(i)?;
-    let (i, _) = char(',')(i)?;
-    let (i, _) = space0(i)?;
+fn identifier(i: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_')(i)
+}
 
-    Ok((i, ()))
+/// A quoted string. Quotes inside the string are escaped by doubling them.
+fn quoted(quote: char) -> impl Fn(&str) -> IResult<&str, Value> {
+    move |i: &str| {
+        let escaped = if quote == '"' { "\"\"" } else { "''" };
+        let not_quote = if quote == '"' { "\"" } else { "'" };
+
+        delimited(
+            char(quote),
+            fold_many0(
+                alt((constant(not_quote, tag(escaped)), is_not(not_quote))),
+                String::new(),
+                |mut acc, s| {
+                    acc.push_str(s);
+                    acc
+                },
+            ),
+            char(quote),
+        )(i)
+        .map(|(i, s)| (i, Value::String(s)))
+    }
 }
 
-fn list(i: &str) -> IResult<&str, Value> {
-    let (i, _) = char('{')(i)?;
-    let (i, v) = map(separated_list(list_separator, value_in_list), Value::List)(i)?;
-    let (i, _) = char('{')(i)?;
+fn string(i: &str) -> IResult<&str, Value> {
+    alt((quoted('"'), quoted('\'')))(i)
+}
 
-    Ok((i, v))
+fn number(i: &str) -> IResult<&str, Value> {
+    map_res(
+        take_while1(|c: char| c.is_ascii_digit() || "+-.eE".contains(c)),
+        |s: &str| match s.parse::<i64>() {
+            Ok(n) => Ok(Value::Number(n)),
+            Err(_) => s.parse::<f64>().map(Value::Float),
+        },
+    )(i)
 }
 
-fn class(i: &str) -> IResult<&str, (&str, Value)> {
-    let (i, (_, _, _, klass, _, map, _)) = tuple((
-        space0,
-        tag("class"),
-        space0,
-        alphanumeric1,
-        char('{'),
-        parser,
-        tag("};"),
-    ))(i)?;
+fn boolean(i: &str) -> IResult<&str, Value> {
+    alt((
+        constant(Value::Boolean(true), tag_no_case("true")),
+        constant(Value::Boolean(false), tag_no_case("false")),
+    ))(i)
+}
 
-    Ok((i, (klass, Value::Class(map))))
+/// An unquoted value like `difficulty = Regular;`.
+fn bare(i: &str) -> IResult<&str, Value> {
+    map(is_not(";,}\r\n"), |s: &str| {
+        Value::String(s.trim().to_owned())
+    })(i)
 }
 
-fn value_in_list(i: &str) -> IResult<&str, Value> {
-    alt((string, number, float, boolean))(i)
+/// A value followed by a delimiter, so `1abc` is not read as a number.
+fn scalar(i: &str) -> IResult<&str, Value> {
+    let end = |i| {
+        let (_, _) = pair(ws, alt((char(';'), char(','), char('}'))))(i)?;
+        Ok((i, ()))
+    };
+
+    alt((
+        string,
+        terminated(number, end),
+        terminated(boolean, end),
+        verify(bare, |v| v != &Value::String(String::new())),
+    ))(i)
 }
 
-fn value(i: &str) -> IResult<&str, Value> {
-    alt((string, number, float, boolean, list, class))(i)
+fn list_value(i: &str) -> IResult<&str, Value> {
+    alt((list, scalar))(i)
 }
 
-fn equals(i: &str) -> IResult<&str, (&str, char, &str)> {
-    tuple((space0, char('='), space0))(i)
+fn list(i: &str) -> IResult<&str, Value> {
+    map(
+        delimited(
+            pair(char('{'), ws),
+            terminated(
+                separated_list(tuple((ws, char(','), ws)), list_value),
+                opt(pair(ws, char(','))),
+            ),
+            pair(ws, char('}')),
+        ),
+        Value::List,
+    )(i)
 }
 
-fn key(i: &str) -> IResult<&str, &str> {
-    unimplemented!();
+/// The `;` terminating an entry. It may be left out for the last entry of a class.
+fn entry_end(i: &str) -> IResult<&str, ()> {
+    alt((constant((), char(';')), constant((), peek(char('}')))))(i)
 }
 
-fn kv_end(i: &str) -> IResult<&str, char> {
-    char(';')(i)
+fn assignment(i: &str) -> IResult<&str, Item<'_>> {
+    let (i, (key, _, _, _, value, _, _)) =
+        tuple((identifier, ws, char('='), ws, scalar, ws, entry_end))(i)?;
+
+    Ok((i, Item::Entry(key, value)))
 }
 
-fn comment(i: &str) -> IResult<&str, String> {
-    let (i, _) = tag("//")(i)?;
-    fold_many0(not_line_ending, String::new(), |mut acc, x| {
-        acc.push_str(x);
-        acc
-    })(i)
+fn array(i: &str) -> IResult<&str, Item<'_>> {
+    let (i, (key, _, _, _, _, _, value, _, _)) = tuple((
+        identifier,
+        ws,
+        tag("[]"),
+        ws,
+        alt((tag("+="), tag("="))),
+        ws,
+        list,
+        ws,
+        entry_end,
+    ))(i)?;
+
+    Ok((i, Item::Entry(key, value)))
 }
 
-fn key_value(i: &str) -> IResult<&str, (&str, Value)> {
-    terminated(separated_pair(key, equals, value), kv_end)(i)
+fn class(i: &str) -> IResult<&str, Item<'_>> {
+    let (i, (_, _, name, _, _parent, _, body, _, _)) = tuple((
+        tag("class"),
+        ws,
+        identifier,
+        ws,
+        opt(preceded(pair(char(':'), ws), identifier)),
+        ws,
+        opt(delimited(pair(char('{'), ws), items, pair(ws, char('}')))),
+        ws,
+        char(';'),
+    ))(i)?;
+
+    Ok((i, Item::Entry(name, Value::Class(body.unwrap_or_default()))))
 }
 
-fn line(i: &str) -> IResult<&str, (Option<(&str, Value)>, Option<String>)> {
-    tuple((opt(key_value), opt(comment)))(i)
+fn delete(i: &str) -> IResult<&str, Item<'_>> {
+    constant(
+        Item::Ignored,
+        tuple((tag("delete"), ws, identifier, ws, char(';'))),
+    )(i)
 }
 
-fn parser(i: &str) -> IResult<&str, HashMap<String, Value>> {
-    fold_many0(line, HashMap::new(), |mut acc, (kv, _)| {
-        if let Some((key, value)) = kv {
-            acc.insert(key.to_string(), value);
-        };
+fn item(i: &str) -> IResult<&str, Item<'_>> {
+    alt((
+        class,
+        delete,
+        array,
+        assignment,
+        constant(Item::Ignored, char(';')),
+    ))(i)
+}
+
+fn items(i: &str) -> IResult<&str, HashMap<String, Value>> {
+    fold_many0(terminated(item, ws), HashMap::new(), |mut acc, item| {
+        if let Item::Entry(key, value) = item {
+            acc.insert(key.to_owned(), value);
+        }
         acc
     })(i)
 }
 
+fn parser(i: &str) -> IResult<&str, HashMap<String, Value>> {
+    preceded(ws, items)(i)
+}
+
 impl Cfg {
-    pub fn from_string(s: &'static str) -> Result<Self> {
-        let (_, map) = all_consuming(parser)(s)?;
+    pub fn from_string(s: &str) -> Result<Self> {
+        let (_, map) = all_consuming(parser)(s).map_err(|err| {
+            anyhow!(
+                "Could not parse config: {}",
+                match err {
+                    nom::Err::Error((i, kind)) | nom::Err::Failure((i, kind)) =>
+                        format!("{:?} at `{}`", kind, i.chars().take(40).collect::<String>()),
+                    nom::Err::Incomplete(_) => "unexpected end of input".to_owned(),
+                }
+            )
+        })?;
         Ok(Self(Value::Class(map)))
     }
+
+    pub fn root(&self) -> &Value {
+        &self.0
+    }
 }
 
 #[cfg(test)]
@@ -141,6 +281,7 @@ mod test {
     use super::*;
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_full() {
         let mut map = HashMap::new();
         map.insert("foo".into(), Value::String("bar".into()));
@@ -173,8 +314,11 @@ class table    {subkey=1};
 // comment
 working        = false;
 "#;
+
+        assert_eq!(Cfg::from_string(s).unwrap(), config);
     }
 
+    #[test]
     fn test_real() {
         let server_config = r#"
 //
@@ -271,9 +415,28 @@ onHackedData		= "kick (_this select 0)";	// command to run if a player has tampe
 headlessClients[]	= {"127.0.0.1"};	// list of IP addresses allowed to connect using headless clients; example: {"127.0.0.1", "192.168.1.100"};
 localClient[]		= {"127.0.0.1"};	// list of IP addresses to which are granted unlimited bandwidth;  example: {"127.0.0.1", "192.168.1.100"};
 "#;
+        let config = Cfg::from_string(server_config).unwrap();
+        let root = config.root();
+
+        assert_eq!(
+            root.get("hostname"),
+            Some(&Value::String("My Arma 3 Server".into()))
+        );
+        assert_eq!(root.get("maxPlayers"), Some(&Value::Number(40)));
+        assert_eq!(root.get("voteThreshold"), Some(&Value::Float(0.33)));
+        assert_eq!(
+            root.get_path("Missions/Mission1/template"),
+            Some(&Value::String("MyMission.Altis".into()))
+        );
+        assert_eq!(
+            root.get("allowedVoteCmds")
+                .and_then(Value::as_list)
+                .map(|l| l.len()),
+            Some(6)
+        );
         assert_eq!(
-            Cfg(Value::Boolean(false)),
-            Cfg::from_string(server_config).unwrap()
+            root.get("onHackedData"),
+            Some(&Value::String("kick (_this select 0)".into()))
         );
     }
 }
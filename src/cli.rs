@@ -11,7 +11,16 @@ pub fn build_cli() -> App {
                 .short("c")
                 .long("config")
                 .help("Sets a custom config location (default: amraam.toml)")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("instance")
+                .short("i")
+                .long("instance")
+                .help("Selects a server instance from the [instance.<name>] tables")
+                .takes_value(true)
+                .global(true),
         )
         .settings(&[AppSettings::VersionlessSubcommands])
         .subcommands(commands::cli())
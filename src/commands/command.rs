@@ -0,0 +1,15 @@
+use crate::commands::{prelude::*, run};
+
+pub fn cli() -> App {
+    SubCommand::with_name("command")
+        .about("Print the command `run` would execute and where its options come from")
+        .arg(Arg::with_name("option set").takes_value(true))
+        .args(&run::option_args())
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+
+    run::print(&settings, &run::launch(&settings, matches, true)?)
+}
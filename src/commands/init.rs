@@ -1,8 +1,11 @@
 use crate::commands::prelude::*;
-use amraam::{steamcmd::SteamCmd, tools::chown};
+use amraam::{
+    steamcmd::{Branch, SteamCmd},
+    tools::chown,
+};
 use anyhow::{bail, ensure, Context, Result};
 use console::{Style, Term};
-use dialoguer::{Input, Password};
+use dialoguer::{Input, Password, Select};
 use lazy_static::lazy_static;
 use pwd::Passwd;
 use std::{
@@ -105,8 +108,26 @@ pub fn exec(_matches: &ArgMatches) -> Result<()> {
     // install arma server
     term.write_line(&format!("\n[3/{}] Installing Arma 3 via SteamCMD", STEPS))?;
 
+    let branches = Branch::ALL
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>();
+    let branch = Branch::ALL[Select::new()
+        .with_prompt("Server branch")
+        .items(&branches)
+        .default(
+            Branch::ALL
+                .iter()
+                .position(|&b| b == Branch::default())
+                .unwrap_or(0),
+        )
+        .interact()
+        .context(InitError::InputInteract)?];
+
+    settings.insert("server.branch".into(), branch.to_string().into());
+
     let server_path = target_path.join("arma3");
-    install_server(server_path, &user.name)?;
+    install_server(server_path, &user.name, branch)?;
 
     // save config
     term.write_line(&format!("[4/{}] Saving config", STEPS))?;
@@ -264,7 +285,7 @@ fn install_steamcmd_debian(bitness: os_info::Bitness, debian: bool) -> Result<()
     Ok(())
 }
 
-fn install_server<P>(path: P, user: &str) -> Result<()>
+fn install_server<P>(path: P, user: &str, branch: Branch) -> Result<()>
 where
     P: AsRef<Path>,
 {
@@ -293,7 +314,7 @@ where
     );
 
     steamcmd
-        .update_arma()
+        .update_arma(branch)
         .context("Could not install ArmA 3 Server")?;
 
     Ok(())
@@ -0,0 +1,133 @@
+use crate::commands::prelude::*;
+use amraam::rpt::Report;
+use console::{style, Term};
+use flate2::read::GzDecoder;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// How many script errors are listed.
+const MAX_SCRIPT_ERRORS: usize = 20;
+
+pub fn cli() -> App {
+    SubCommand::with_name("analyze")
+        .about("Summarize a RPT log")
+        .long_about(
+            "Summarize the loaded mods, missing addons, script errors, object not found messages, \
+            BattlEye kicks, players and server FPS of a RPT log. Archived logs ending in .gz can \
+            be read directly.",
+        )
+        .arg(Arg::with_name("file").required(true).help("The RPT log"))
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let path = Path::new(args.value_of("file").context("Missing file")?);
+    let file =
+        File::open(path).with_context(|| format!("Could not open log {}", path.display()))?;
+
+    let reader: Box<dyn BufRead> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(BufReader::new(GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let report = Report::parse(reader).context("Could not analyze log")?;
+
+    print(&report)
+}
+
+fn print(report: &Report) -> Result<()> {
+    let term = Term::buffered_stdout();
+
+    term.write_line(&format!("{} ({})", style("Mods").bold(), report.mods.len()))?;
+    for m in &report.mods {
+        term.write_line(&format!(
+            "  {:<40} {:<20} {}",
+            m.name,
+            m.version.as_deref().unwrap_or("-"),
+            style(&m.dir).dim()
+        ))?;
+    }
+
+    term.write_line(&format!(
+        "\n{} ({})",
+        style("Missing addons").bold(),
+        report.missing_addons.len()
+    ))?;
+    for missing in &report.missing_addons {
+        term.write_line(&format!(
+            "  {} required by {}",
+            style(&missing.addon).red(),
+            missing.required_by
+        ))?;
+    }
+
+    term.write_line(&format!(
+        "\n{} ({} distinct, {} total)",
+        style("Script errors").bold(),
+        report.script_errors.len(),
+        report
+            .script_errors
+            .iter()
+            .map(|error| error.count)
+            .sum::<usize>()
+    ))?;
+    for error in report.script_errors.iter().take(MAX_SCRIPT_ERRORS) {
+        term.write_line(&format!(
+            "  {:>5}x {}:{}",
+            error.count, error.file, error.line
+        ))?;
+        term.write_line(&format!("         {}", style(&error.message).dim()))?;
+    }
+    if report.script_errors.len() > MAX_SCRIPT_ERRORS {
+        term.write_line(&format!(
+            "  ... and {} more",
+            report.script_errors.len() - MAX_SCRIPT_ERRORS
+        ))?;
+    }
+
+    term.write_line(&format!(
+        "\n{} {} (at most {} per minute)",
+        style("Object not found messages").bold(),
+        report.objects_not_found,
+        report.objects_not_found_peak
+    ))?;
+
+    term.write_line(&format!(
+        "\n{} ({})",
+        style("BattlEye kicks").bold(),
+        report.kicks.len()
+    ))?;
+    for kick in &report.kicks {
+        term.write_line(&format!("  {:<32} {}", kick.player, kick.reason))?;
+    }
+
+    term.write_line(&format!(
+        "\n{} {} connects, {} disconnects, {} players",
+        style("Players").bold(),
+        report.connects,
+        report.disconnects,
+        report.players.len()
+    ))?;
+
+    match report.fps_range() {
+        Some((min, average, max)) => term.write_line(&format!(
+            "\n{} min {} / avg {:.1} / max {} over {} samples",
+            style("Server FPS").bold(),
+            min,
+            average,
+            max,
+            report.fps.len()
+        ))?,
+        None => term.write_line(&format!(
+            "\n{} no samples, enable them with #monitords",
+            style("Server FPS").bold()
+        ))?,
+    }
+
+    term.flush().context("Could not flush terminal")?;
+
+    Ok(())
+}
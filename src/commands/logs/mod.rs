@@ -0,0 +1,145 @@
+use crate::commands::prelude::*;
+use amraam::logs::{self, LogKind};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// How often a followed log is checked for new lines.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn cli() -> App {
+    SubCommand::with_name("logs")
+        .about("Show the current log of a server")
+        .long_about(
+            "Show the end of the newest RPT file in the profiles directory of the server. Logs \
+            are only managed for servers with a profiles directory, which instances always have. \
+            RPT files are archived when the server starts, the one the server is writing is not \
+            limited by logs.max_size or logs.max_age until it is restarted.",
+        )
+        .args(&[
+            Arg::with_name("option set").takes_value(true),
+            Arg::with_name("follow").short("f").long("follow").help(
+                "Keep printing new lines, switching to the next log when the server restarts",
+            ),
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .help("Show the captured output of the server instead of the RPT file"),
+            Arg::with_name("lines")
+                .short("n")
+                .long("lines")
+                .takes_value(true)
+                .default_value("20")
+                .help("Number of lines to show"),
+        ])
+        .subcommand(analyze::cli())
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    if let ("analyze", Some(sub_args)) = matches.subcommand() {
+        return analyze::exec(sub_args);
+    }
+
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let instance =
+        settings.get_instance(matches.value_of("instance"), matches.value_of("option set"))?;
+
+    let dir = logs::log_dir(&server_path, &instance.options).with_context(|| {
+        format!(
+            "Server {} has no profiles directory, set `profiles` to manage its logs",
+            instance.name
+        )
+    })?;
+
+    let kind = if matches.is_present("output") {
+        LogKind::Output
+    } else {
+        LogKind::Rpt
+    };
+
+    let lines = matches
+        .value_of("lines")
+        .unwrap_or("20")
+        .parse()
+        .context("Lines is not a number")?;
+
+    let path = logs::current(&dir, kind)?
+        .with_context(|| format!("There are no logs in {}", dir.display()))?;
+
+    let mut file = tail(&path, lines)?;
+
+    if matches.is_present("follow") {
+        follow(&dir, kind, path, &mut file)?;
+    }
+
+    Ok(())
+}
+
+/// Print the last `lines` lines of the log and get the file positioned at its end.
+fn tail(path: &Path, lines: usize) -> Result<File> {
+    let mut file =
+        File::open(path).with_context(|| format!("Could not open log {}", path.display()))?;
+
+    let mut last = VecDeque::with_capacity(lines + 1);
+    for line in BufReader::new(&mut file).split(b'\n') {
+        last.push_back(line.context("Could not read log")?);
+        if last.len() > lines {
+            last.pop_front();
+        }
+    }
+
+    let mut stdout = io::stdout();
+    for line in last {
+        stdout.write_all(&line)?;
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()?;
+
+    file.seek(SeekFrom::End(0))
+        .context("Could not seek to end of log")?;
+
+    Ok(file)
+}
+
+/// Print lines appended to the log until interrupted.
+fn follow(dir: &Path, kind: LogKind, mut path: PathBuf, file: &mut File) -> Result<()> {
+    let mut stdout = io::stdout();
+    let mut buf = Vec::new();
+
+    loop {
+        buf.clear();
+        file.read_to_end(&mut buf).context("Could not read log")?;
+
+        if !buf.is_empty() {
+            stdout.write_all(&buf)?;
+            stdout.flush()?;
+            continue;
+        }
+
+        // a restarted server writes to a new log
+        if let Some(newest) = logs::current(dir, kind)? {
+            if newest != path {
+                *file = File::open(&newest)
+                    .with_context(|| format!("Could not open log {}", newest.display()))?;
+                writeln!(stdout, "==> {} <==", newest.display())?;
+                path = newest;
+                continue;
+            }
+        }
+
+        thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+pub mod analyze;
@@ -0,0 +1,100 @@
+use crate::commands::prelude::*;
+use amraam::{
+    addons::{self, AddonIndex},
+    config::modpack::Mod,
+    mission::{find_mission, Mission},
+    Settings,
+};
+use console::{style, Term};
+use std::path::Path;
+
+pub fn cli() -> App {
+    SubCommand::with_name("check")
+        .about("Check that a modpack provides all addons a mission requires")
+        .long_about(
+            "Compare the addons listed in the mission.sqm of a mission with the CfgPatches of \
+            the PBOs in the base game and the mods of a modpack.",
+        )
+        .args(&[
+            Arg::with_name("name")
+                .required(true)
+                .takes_value(true)
+                .help("The file name of the mission"),
+            Arg::with_name("modpack")
+                .short("m")
+                .long("modpack")
+                .required(true)
+                .takes_value(true)
+                .help("The modpack the mission is played with"),
+        ])
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let settings =
+        Settings::from_path(args.value_of("config")).context("Could not load settings")?;
+    let term = Term::stdout();
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let mission_name = args
+        .value_of("name")
+        .context("Could not get mission name from arguments")?;
+    let modpack_name = args
+        .value_of("modpack")
+        .context("Could not get modpack name from arguments")?;
+
+    let mission = Mission::open(find_mission(
+        Path::new(&server_path).join("mpmissions"),
+        mission_name,
+    )?)?;
+    let required = addons::mission_requirements(&mission.mission_sqm()?);
+
+    let names = settings
+        .get_modpack_mods(modpack_name, &server_path)?
+        .iter()
+        .map(Mod::dir_name)
+        .collect::<Result<Vec<String>>>()?;
+    let names = names.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let mut index =
+        AddonIndex::load_or_build(&server_path, false).context("Could not load addon index")?;
+    index.update_stale(&server_path, &names)?;
+
+    let provided = index.base_game.iter().chain(
+        names
+            .iter()
+            .filter_map(|&name| index.mods.get(name))
+            .flatten(),
+    );
+    let missing = addons::missing(&required, provided);
+
+    if missing.is_empty() {
+        term.write_line(&format!(
+            " {} all {} addons required by {} are provided by {}",
+            style("OK").green().bold(),
+            required.len(),
+            mission.name,
+            modpack_name
+        ))?;
+        return Ok(());
+    }
+
+    term.write_line(&format!(
+        " {} {} of {} addons required by {}:",
+        style("Missing").red().bold(),
+        missing.len(),
+        required.len(),
+        mission.name
+    ))?;
+
+    for addon in &missing {
+        term.write_line(&format!("  {}", addon))?;
+    }
+
+    bail!(
+        "Modpack {} does not provide all required addons",
+        modpack_name
+    )
+}
@@ -1,9 +1,114 @@
 use crate::commands::prelude::*;
+use amraam::{
+    mission::{find_mission, Mission, MissionName},
+    tools::{chmod, chown},
+    Settings,
+};
+use console::{style, Term};
+use std::{fs, path::Path};
 
 pub fn cli() -> App {
     SubCommand::with_name("fix")
+        .about("Fix a mission installation")
+        .long_about(
+            "Fix a mission installation. The mission is always checked for problems like a \
+            missing mission.sqm or a corrupt PBO.",
+        )
+        .arg(
+            Arg::with_name("name")
+                .required(true)
+                .takes_value(true)
+                .help("The file name of the mission"),
+        )
+        .arg(
+            Arg::with_name("rename")
+                .short("l")
+                .long("rename")
+                .help("Rename the mission to lowercase while keeping the map name"),
+        )
+        .arg(
+            Arg::with_name("permissions")
+                .short("p")
+                .long("permissions")
+                .help("Fix permissions"),
+        )
+        .arg(
+            Arg::with_name("owner")
+                .short("o")
+                .long("owner")
+                .help("Fix owner"),
+        )
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
-    unimplemented!()
+    match sudo::escalate_if_needed() {
+        Ok(_) => {}
+        Err(err) => bail!("Could not escalate with sudo: {}", err),
+    };
+
+    let settings =
+        Settings::from_path(args.value_of("config")).context("Could not load settings")?;
+    let term = Term::stdout();
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let mpmissions_path = Path::new(&server_path).join("mpmissions");
+
+    let mission_name = args
+        .value_of("name")
+        .context("Could not get mission name from arguments")?;
+
+    let mission_path = find_mission(&mpmissions_path, mission_name)?;
+    let mut mission = Mission::open(&mission_path)?;
+
+    let problems = mission.problems();
+    for problem in &problems {
+        term.write_line(&format!(" {} {}", style("Problem").red().bold(), problem))?;
+    }
+
+    if args.is_present("rename") {
+        let name = MissionName {
+            name: mission.name.name.to_lowercase(),
+            ..mission.name.clone()
+        };
+        let target_path = mpmissions_path.join(name.file_name());
+
+        if target_path != mission.path {
+            ensure!(
+                !target_path.exists(),
+                "Cannot rename mission since {} already exists",
+                target_path.display()
+            );
+
+            fs::rename(&mission.path, &target_path).context("Could not rename mission")?;
+
+            term.write_line(&format!(
+                " Renamed {} to {}",
+                mission.name.file_name(),
+                name.file_name()
+            ))?;
+
+            mission = Mission::open(&target_path)?;
+        }
+    }
+
+    if args.is_present("permissions") {
+        chmod(&mission.path, 0o755, 0o644, true)
+            .context("Could not change permissions of mission")?;
+    }
+
+    if args.is_present("owner") {
+        let name = settings
+            .get_str("server.user")
+            .context("Could not get server user from config")?
+            .context("Missing key `server.user`")?;
+
+        chown(&mission.path, &name, true).context("Could not change ownernship of mission")?
+    }
+
+    ensure!(problems.is_empty(), "Mission is broken");
+
+    Ok(())
 }
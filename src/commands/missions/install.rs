@@ -1,8 +1,15 @@
 use crate::commands::prelude::*;
-use amraam::tools::{chmod, chown};
+use amraam::{
+    mission::MissionName,
+    pbo::{Pbo, PboWriter},
+    tools::{chmod, chown, remove_path, replace_path},
+    util::{PREVIOUS_SUFFIX, STAGED_SUFFIX},
+};
 use console::Term;
+use fs_extra::dir::{copy, CopyOptions};
+use nix::errno::Errno;
 use std::{
-    fs::{create_dir_all, rename},
+    fs::{self, create_dir_all, rename},
     path::Path,
 };
 use thiserror::Error;
@@ -22,11 +29,28 @@ pub enum InstallError {
 pub fn cli() -> App {
     SubCommand::with_name("install")
         .about("Install a mission")
-        .long_about("Move or copy a mission into the mpmissions directory.")
-        .args(&[Arg::with_name("path")
-            .required(true)
-            .takes_value(true)
-            .help("The path to the mission you want to install")])
+        .long_about(
+            "Move or copy a mission into the mpmissions directory. The mission can either be a \
+            directory or a packed .pbo file and must be named `<name>.<map>`.",
+        )
+        .args(&[
+            Arg::with_name("path")
+                .required(true)
+                .takes_value(true)
+                .help("The path to the mission you want to install"),
+            Arg::with_name("keep")
+                .short("k")
+                .long("keep")
+                .help("Keep the original files"),
+            Arg::with_name("force")
+                .short("f")
+                .long("force")
+                .help("Force overwriting an existing mission"),
+            Arg::with_name("pack")
+                .short("p")
+                .long("pack")
+                .help("Pack a mission directory into a PBO"),
+        ])
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
@@ -43,7 +67,25 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
         .canonicalize()
         .context(InstallError::Canonicalize)?;
 
-    ensure!(mission_path.is_dir(), "Mod path is not a directory");
+    let mut mission = MissionName::from_path(&mission_path)?;
+
+    if mission.packed {
+        ensure!(mission_path.is_file(), "Mission path is not a file");
+        ensure!(!args.is_present("pack"), "Mission is already packed");
+
+        Pbo::open(&mission_path).context("Mission is not a valid PBO")?;
+    } else {
+        ensure!(
+            mission_path.is_dir(),
+            "Mission path is neither a directory nor a .pbo file"
+        );
+        ensure!(
+            mission_path.join("mission.sqm").is_file(),
+            "Mission directory does not contain a mission.sqm"
+        );
+
+        mission.packed = args.is_present("pack");
+    }
 
     let server_path = settings
         .get_server_path()
@@ -62,21 +104,78 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
         chmod(&mpmissions_path, 0o755, 0o644, true).context(InstallError::Chmod)?;
     }
 
-    let name = mission_path
-        .file_name()
-        .context("Path does not point to a file or directory")?;
+    let target_path = mpmissions_path.join(mission.file_name());
+
+    if target_path.exists() {
+        ensure!(
+            args.is_present("force"),
+            "Mission already exists. If you want to overwrite it pass --force"
+        );
+    }
+
+    // the new mission is staged next to the target so an existing mission is only replaced once
+    // it is complete
+    let staged_path = mpmissions_path.join(format!(".{}{}", mission.file_name(), STAGED_SUFFIX));
+    remove_path(&staged_path).context("Could not remove previously staged mission")?;
+
+    if args.is_present("pack") {
+        PboWriter::new()
+            .add_dir(&mission_path)?
+            .write_file(&staged_path)
+            .context("Could not pack mission")?;
+    } else if args.is_present("keep") {
+        copy_mission(&mission_path, &staged_path).context("Could not copy mission")?;
+    } else {
+        move_mission(&mission_path, &staged_path)
+            .context("Could not move mission to mpmissions")?;
+    }
 
-    let target_path = mpmissions_path.join(name);
+    let previous_path =
+        mpmissions_path.join(format!(".{}{}", mission.file_name(), PREVIOUS_SUFFIX));
+    replace_path(&staged_path, &target_path, &previous_path)
+        .context("Could not replace old mission")?;
+    remove_path(&previous_path).context("Could not remove old mission")?;
 
-    rename(&mission_path, &target_path).context("Could not move mission to mpmissions")?;
+    if args.is_present("pack") && !args.is_present("keep") {
+        fs::remove_dir_all(&mission_path).context("Could not remove packed mission")?;
+    }
 
     chown(&target_path, &user, true).context(InstallError::Chown)?;
     chmod(&target_path, 0o755, 0o644, true).context(InstallError::Chmod)?;
 
-    Term::stdout().write_line(&format!(
-        "Sucessfully installed {}",
-        &name.to_string_lossy()
-    ))?;
+    Term::stdout().write_line(&format!("Sucessfully installed {}", mission))?;
 
     Ok(())
 }
+
+fn copy_mission(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        let options = CopyOptions {
+            copy_inside: true,
+            ..CopyOptions::new()
+        };
+        copy(from, to, &options)?;
+    } else {
+        fs::copy(from, to)?;
+    }
+
+    Ok(())
+}
+
+/// Move a mission, copying it if `to` is on another filesystem.
+fn move_mission(from: &Path, to: &Path) -> Result<()> {
+    match rename(from, to) {
+        Err(err) if err.raw_os_error() == Some(Errno::EXDEV as i32) => {
+            debug!("{} is on another filesystem, copying it", from.display());
+
+            if let Err(err) = copy_mission(from, to) {
+                remove_path(to).ok();
+                return Err(err);
+            }
+
+            remove_path(from).context("Could not remove original mission")?;
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
@@ -1,9 +1,174 @@
 use crate::commands::prelude::*;
+use amraam::mission::{list_missions, Mission, MissionRotation};
+use console::{style, Term};
+use indicatif::HumanBytes;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize)]
+struct MissionInfo {
+    file: String,
+    name: Option<String>,
+    terrain: Option<String>,
+    packed: bool,
+    size: u64,
+    /// Unknown if the server config could not be read.
+    in_rotation: Option<bool>,
+    in_whitelist: Option<bool>,
+    briefing_name: Option<String>,
+}
 
 pub fn cli() -> App {
     SubCommand::with_name("list")
+        .about("List all installed missions")
+        .args(&[
+            Arg::with_name("option set")
+                .takes_value(true)
+                .help("The option set whose server config is checked for the mission rotation"),
+            Arg::with_name("json")
+                .short("j")
+                .long("json")
+                .help("Print the missions as JSON"),
+        ])
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
-    unimplemented!()
+    let settings =
+        Settings::from_path(args.value_of("config")).context("Could not load settings")?;
+    let term = Term::buffered_stdout();
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let mpmissions_path = Path::new(&server_path).join("mpmissions");
+
+    let options = settings
+        .get_instance(args.value_of("instance"), args.value_of("option set"))?
+        .options;
+
+    let rotation = match options.config {
+        Some(name) => match settings.get_server_config(&name).and_then(|config| {
+            MissionRotation::load(Path::new(&server_path).join(format!("{}.cfg", config)))
+        }) {
+            Ok(rotation) => Some(rotation),
+            Err(err) => {
+                // the missions can still be listed, only their rotation state is unknown
+                Term::stderr()
+                    .write_line(&format!("{}: {:?}", style("Warning").yellow(), err))
+                    .context("Could not write line on terminal")?;
+                None
+            }
+        },
+        None => Some(MissionRotation::default()),
+    };
+
+    let missions = list_missions(&mpmissions_path)
+        .context("Could not list missions")?
+        .into_iter()
+        .map(|path| mission_info(&path, rotation.as_ref()))
+        .collect::<Result<Vec<MissionInfo>>>()?;
+
+    if args.is_present("json") {
+        term.write_line(
+            &serde_json::to_string_pretty(&missions).context("Could not serialize missions")?,
+        )
+        .context("Could not write line on terminal")?;
+        term.flush().context("Could not flush terminal")?;
+
+        return Ok(());
+    }
+
+    term.write_line(&format!(
+        "Installed missions in {}:",
+        mpmissions_path.display()
+    ))
+    .context("Could not write header line on terminal")?;
+
+    for mission in missions {
+        let (name, terrain) = match (mission.name, mission.terrain) {
+            (Some(name), Some(terrain)) => (name, terrain),
+            _ => {
+                term.write_line(&format!(
+                    " {} {}",
+                    mission.file,
+                    style("(invalid name)").red()
+                ))
+                .context("Could not write line on terminal")?;
+                continue;
+            }
+        };
+
+        let flags = match (mission.in_rotation, mission.in_whitelist) {
+            (Some(in_rotation), Some(in_whitelist)) => {
+                let mut flags = Vec::new();
+                if in_rotation {
+                    flags.push("rotation");
+                }
+                if in_whitelist {
+                    flags.push("whitelist");
+                }
+                style(flags.join(",")).green()
+            }
+            _ => style("unknown".to_owned()).yellow(),
+        };
+
+        term.write_line(&format!(
+            " {:<32} {:<12} {:<6} {:>10} {:<20} {}",
+            name,
+            terrain,
+            if mission.packed { "pbo" } else { "folder" },
+            HumanBytes(mission.size).to_string(),
+            flags,
+            mission.briefing_name.unwrap_or_default()
+        ))
+        .context("Could not write line on terminal")?;
+    }
+
+    term.flush().context("Could not flush terminal")?;
+
+    Ok(())
+}
+
+fn mission_info(path: &Path, rotation: Option<&MissionRotation>) -> Result<MissionInfo> {
+    let file = path
+        .file_name()
+        .context("Path has no filename")?
+        .to_string_lossy()
+        .into_owned();
+
+    let mission = match Mission::open(path) {
+        Ok(mission) => mission,
+        Err(_) => {
+            return Ok(MissionInfo {
+                file,
+                name: None,
+                terrain: None,
+                packed: path.is_file(),
+                size: 0,
+                in_rotation: None,
+                in_whitelist: None,
+                briefing_name: None,
+            })
+        }
+    };
+
+    let briefing_name = match mission.briefing_name() {
+        Ok(name) => name,
+        Err(err) => {
+            debug!("Could not read briefing name of {}: {:?}", file, err);
+            None
+        }
+    };
+
+    Ok(MissionInfo {
+        name: Some(mission.name.name.clone()),
+        terrain: Some(mission.name.map.clone()),
+        packed: mission.name.packed,
+        size: mission.size().context("Could not get size of mission")?,
+        in_rotation: rotation.map(|rotation| rotation.in_rotation(&mission.name)),
+        in_whitelist: rotation.map(|rotation| rotation.in_whitelist(&mission.name)),
+        briefing_name,
+        file,
+    })
 }
@@ -3,16 +3,26 @@ use crate::commands::prelude::*;
 pub fn cli() -> App {
     SubCommand::with_name("missions")
         .about("Manage missions")
-        .subcommands(vec![install::cli(), fix::cli(), list::cli()])
+        .subcommands(vec![
+            install::cli(),
+            uninstall::cli(),
+            restore::cli(),
+            check::cli(),
+            fix::cli(),
+            list::cli(),
+        ])
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
     let (cmd, sub_args) = args.subcommand();
 
     let f = match cmd {
+        "check" => check::exec,
         "fix" => fix::exec,
         "install" => install::exec,
         "list" => list::exec,
+        "restore" => restore::exec,
+        "uninstall" => uninstall::exec,
         _ => {
             cli().print_help()?;
             return Ok(());
@@ -22,6 +32,9 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
     f(sub_args.context("Missing arguments")?)
 }
 
+pub mod check;
 pub mod fix;
 pub mod install;
 pub mod list;
+pub mod restore;
+pub mod uninstall;
@@ -0,0 +1,53 @@
+use crate::commands::prelude::*;
+use amraam::mission::{find_mission, Mission};
+use console::Term;
+use std::{fs::rename, path::Path};
+
+pub fn cli() -> App {
+    SubCommand::with_name("restore")
+        .about("Restore an uninstalled mission")
+        .long_about("Move a mission from the mpmissions.archive directory back into mpmissions.")
+        .arg(
+            Arg::with_name("name")
+                .required(true)
+                .takes_value(true)
+                .help("The name of the mission you want to restore"),
+        )
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    match sudo::escalate_if_needed() {
+        Ok(_) => {}
+        Err(err) => bail!("Could not escalate with sudo: {}", err),
+    };
+
+    let settings =
+        Settings::from_path(args.value_of("config")).context("Could not load settings")?;
+
+    let name = args.value_of("name").context("Missing mission name")?;
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let mpmissions_path = Path::new(&server_path).join("mpmissions");
+    let archive_path = Path::new(&server_path).join("mpmissions.archive");
+
+    let mission = Mission::open(
+        find_mission(&archive_path, name).context("Could not find mission in archive")?,
+    )?;
+
+    let target_path = mpmissions_path.join(mission.name.file_name());
+
+    ensure!(
+        !target_path.exists(),
+        "Mission {} is already installed",
+        mission.name
+    );
+
+    rename(&mission.path, &target_path).context("Could not move mission to mpmissions")?;
+
+    Term::stdout().write_line(&format!("Sucessfully restored {}", mission.name))?;
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use crate::commands::prelude::*;
+use amraam::{
+    mission::{find_mission, Mission, MissionRotation},
+    tools::{chmod, chown},
+};
+use console::Term;
+use std::{
+    collections::BTreeSet,
+    fs::{create_dir_all, rename},
+    path::Path,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum UninstallError {
+    #[error("Could not change ownership of path")]
+    Chown,
+
+    #[error("Could not set permission of path")]
+    Chmod,
+}
+
+pub fn cli() -> App {
+    SubCommand::with_name("uninstall")
+        .about("Uninstall a mission")
+        .long_about(
+            "Move a mission from the mpmissions directory into the mpmissions.archive directory. \
+            Missions that are in the rotation or missionWhitelist of a server config used by any \
+            option set or instance are not uninstalled.",
+        )
+        .arg(
+            Arg::with_name("name")
+                .required(true)
+                .takes_value(true)
+                .help("The name of the mission you want to uninstall"),
+        )
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    match sudo::escalate_if_needed() {
+        Ok(_) => {}
+        Err(err) => bail!("Could not escalate with sudo: {}", err),
+    };
+
+    let settings =
+        Settings::from_path(args.value_of("config")).context("Could not load settings")?;
+
+    let name = args.value_of("name").context("Missing mission name")?;
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let mpmissions_path = Path::new(&server_path).join("mpmissions");
+    let archive_path = Path::new(&server_path).join("mpmissions.archive");
+
+    let mission = Mission::open(find_mission(&mpmissions_path, name)?)?;
+
+    for config in server_configs(&settings)? {
+        let config_path = Path::new(&server_path).join(format!("{}.cfg", config));
+        if !config_path.is_file() {
+            continue;
+        }
+
+        let rotation = MissionRotation::load(&config_path)?;
+
+        ensure!(
+            !rotation.in_rotation(&mission.name),
+            "Mission {} is in the rotation of server config {}",
+            mission.name,
+            config
+        );
+        ensure!(
+            !rotation.in_whitelist(&mission.name),
+            "Mission {} is in the missionWhitelist of server config {}",
+            mission.name,
+            config
+        );
+    }
+
+    let user = settings
+        .get_str("server.user")
+        .context("Could not get server user from config")?
+        .context("Missing config key server.user")?;
+
+    if !archive_path.exists() {
+        create_dir_all(&archive_path).context("Could not create mpmissions.archive directory")?;
+        chown(&archive_path, &user, true).context(UninstallError::Chown)?;
+        chmod(&archive_path, 0o755, 0o644, true).context(UninstallError::Chmod)?;
+    }
+
+    let target_path = archive_path.join(mission.name.file_name());
+
+    ensure!(
+        !target_path.exists(),
+        "Mission {} is already archived. Remove {} first",
+        mission.name,
+        target_path.display()
+    );
+
+    rename(&mission.path, &target_path).context("Could not move mission to archive")?;
+
+    Term::stdout().write_line(&format!("Sucessfully uninstalled {}", mission.name))?;
+
+    Ok(())
+}
+
+/// The server configs of all option sets and instances.
+fn server_configs(settings: &Settings) -> Result<BTreeSet<String>> {
+    let option_sets = settings
+        .get_table("options")
+        .context("Could not get option sets from config")?
+        .unwrap_or_default()
+        .into_keys()
+        .map(|name| format!("options.{}.config", name));
+    let instances = settings
+        .get_instance_names()?
+        .into_iter()
+        .map(|name| format!("instance.{}.config", name));
+
+    let mut configs = BTreeSet::new();
+
+    for key in option_sets.chain(instances) {
+        if let Some(config) = settings
+            .get_str(&key)
+            .context("Could not get server config")?
+        {
+            configs.insert(settings.get_server_config(&config)?);
+        }
+    }
+
+    Ok(configs)
+}
@@ -8,6 +8,13 @@ pub fn cli() -> Vec<App> {
         missions::cli(),
         mods::cli(),
         run::cli(),
+        command::cli(),
+        start::cli(),
+        stop::cli(),
+        restart::cli(),
+        status::cli(),
+        logs::cli(),
+        systemd::cli(),
         completions::cli(),
     ]
 }
@@ -19,16 +26,30 @@ pub fn exec(cmd: &str) -> Option<fn(&ArgMatches<'_>) -> Result<()>> {
         "missions" => missions::exec,
         "mods" => mods::exec,
         "run" => run::exec,
+        "command" => command::exec,
+        "start" => start::exec,
+        "stop" => stop::exec,
+        "restart" => restart::exec,
+        "status" => status::exec,
+        "logs" => logs::exec,
+        "systemd" => systemd::exec,
         "generate-completions" => completions::exec,
         _ => return None,
     };
     Some(f)
 }
 
+pub mod command;
 pub mod completions;
 pub mod generate;
 pub mod init;
+pub mod logs;
 pub mod missions;
 pub mod mods;
 pub mod prelude;
+pub mod restart;
 pub mod run;
+pub mod start;
+pub mod status;
+pub mod stop;
+pub mod systemd;
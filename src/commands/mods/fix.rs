@@ -3,6 +3,7 @@ use amraam::{
     tools::{chmod, chown, lowercase},
     Settings,
 };
+use console::Term;
 use std::path::Path;
 
 pub fn cli() -> App {
@@ -20,6 +21,13 @@ pub fn cli() -> App {
                 .long("rename-files")
                 .help("Rename all files to lowercase"),
         )
+        .arg(
+            Arg::with_name("dry run")
+                .short("n")
+                .long("dry-run")
+                .requires("rename files")
+                .help("Only print the files that would be renamed"),
+        )
         .arg(
             Arg::with_name("permissions")
                 .short("p")
@@ -58,7 +66,23 @@ pub fn exec(matches: &ArgMatches) -> Result<()> {
     ensure!(mod_path.exists(), "Mod not found");
 
     if matches.is_present("rename files") {
-        lowercase(&mod_path, true).context("Could not rename files to lowercase")?;
+        let dry_run = matches.is_present("dry run");
+        let renames =
+            lowercase(&mod_path, dry_run).context("Could not rename files to lowercase")?;
+        let term = Term::stdout();
+
+        for rename in &renames {
+            term.write_line(&format!(
+                " {} {} -> {}",
+                if dry_run { "Would rename" } else { "Renamed" },
+                rename.from.display(),
+                rename.to.display()
+            ))?;
+        }
+
+        if dry_run {
+            return Ok(());
+        }
     }
 
     if matches.is_present("permissions") {
@@ -1,10 +1,27 @@
 use crate::commands::prelude::*;
-use amraam::tools::{chmod, chown, lowercase};
+use amraam::{
+    addons::AddonIndex,
+    archive::{self, ArchiveKind},
+    tools::{chmod, chown, lowercase, replace_path},
+    util::{list_previous, PREVIOUS_SUFFIX, STAGED_SUFFIX},
+};
 use console::Term;
-use fs_extra::dir::{copy_with_progress, CopyOptions};
+use fs_extra::{
+    copy_items_with_progress,
+    dir::{CopyOptions, TransitProcessResult},
+};
 use indicatif::{HumanBytes, ProgressBar};
-use std::{fs, path::Path};
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 use thiserror::Error;
+use walkdir::WalkDir;
+
+/// Previous versions of updated mods are kept for a week unless configured otherwise.
+const DEFAULT_KEEP_PREVIOUS_DAYS: i64 = 7;
 
 #[derive(Debug, Error)]
 pub enum InstallError {
@@ -16,17 +33,23 @@ pub enum InstallError {
 
     #[error("Could not set permission of path")]
     Chmod,
+
+    #[error("Could not extract archive")]
+    Extract,
 }
 
 pub fn cli() -> App {
     SubCommand::with_name("install")
         .about("Install a mod")
-        .long_about("Move a mod to the mods directory.")
+        .long_about(
+            "Move a mod to the mods directory. The mod can either be a directory or a .zip, \
+            .tar.gz or .7z archive containing the mod.",
+        )
         .args(&[
             Arg::with_name("path")
                 .required(true)
                 .takes_value(true)
-                .help("The path to the mod you want to install"),
+                .help("The path to the mod or mod archive you want to install"),
             Arg::with_name("name")
                 .short("n")
                 .long("name")
@@ -37,7 +60,7 @@ pub fn cli() -> App {
             Arg::with_name("update")
                 .short("u")
                 .long("update")
-                .help("Update mod by removing the old files first"),
+                .help("Update mod and keep the old version for `mods rollback`"),
             Arg::with_name("rename files")
                 .short("l")
                 .long("rename-files")
@@ -67,8 +90,6 @@ pub fn exec(matches: &ArgMatches) -> Result<()> {
         .canonicalize()
         .context(InstallError::Canonicalize)?;
 
-    ensure!(mod_path.is_dir(), "Mod path is not a directory");
-
     let server_path = settings
         .get_server_path()
         .context("Could not get server path from config")?;
@@ -86,6 +107,37 @@ pub fn exec(matches: &ArgMatches) -> Result<()> {
         chmod(&mods_path, 0o755, 0o644, true).context(InstallError::Chmod)?;
     }
 
+    // archives are extracted into a staging directory next to the mods directory so the
+    // extracted mod can be moved into place like any other directory
+    let staging_path = match ArchiveKind::from_path(&mod_path) {
+        Some(kind) if mod_path.is_file() => {
+            let stem = kind
+                .file_stem(&mod_path)
+                .context("Could not get archive name")?;
+            let staging = Staging(Path::new(&server_path).join(".staging").join(stem));
+
+            if staging.0.exists() {
+                fs::remove_dir_all(&staging.0).context("Could not remove old staging directory")?;
+            }
+
+            archive::extract(&mod_path, &staging.0).context(InstallError::Extract)?;
+
+            Some(staging)
+        }
+        _ => {
+            ensure!(
+                mod_path.is_dir(),
+                "Mod path is neither a directory nor a supported archive (.zip, .tar.gz, .7z)"
+            );
+            None
+        }
+    };
+
+    let mod_path = match &staging_path {
+        Some(staging) => archive::find_mod_root(&staging.0)?,
+        None => mod_path,
+    };
+
     let mods_path = mods_path
         .canonicalize()
         .context(InstallError::Canonicalize)?;
@@ -103,50 +155,180 @@ pub fn exec(matches: &ArgMatches) -> Result<()> {
 
     let target_path = mods_path.join(&name);
 
-    if matches.is_present("update") {
-        if target_path.is_dir() {
-            fs::remove_dir_all(&target_path).context("Could not remove old mod")?;
-        }
+    // updates are installed next to the old version and swapped in once they are complete so
+    // a failed copy never leaves the server without the mod
+    let update = matches.is_present("update") && target_path.is_dir();
+    let install_path = if update {
+        mods_path.join(format!("{}{}", name, STAGED_SUFFIX))
+    } else {
+        target_path.clone()
+    };
+
+    if update && install_path.exists() {
+        fs::remove_dir_all(&install_path).context("Could not remove old staged mod")?;
     }
 
-    if !target_path.is_dir() {
-        fs::create_dir(&target_path).context("Could not create mod directory in mods")?;
-    } else {
-        if !matches.is_present("force") {
-            bail!("Mod directory already exists");
-        }
+    if !install_path.is_dir() {
+        fs::create_dir(&install_path).context("Could not create mod directory in mods")?;
+    } else if !matches.is_present("force") {
+        bail!("Mod directory already exists");
     }
 
-    if matches.is_present("keep") {
+    // the staged copy of an archive is always moved since the archive itself is kept
+    if matches.is_present("keep") && staging_path.is_none() {
         // copy
         let bar = ProgressBar::new_spinner();
         let options = CopyOptions {
             overwrite: matches.is_present("force"),
             ..CopyOptions::new()
         };
+        let items = fs::read_dir(&mod_path)
+            .context("Could not read mod directory")?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
 
-        copy_with_progress(&mod_path, &mods_path, &options, |process_info| {
+        copy_items_with_progress(&items, &install_path, &options, |process_info| {
             bar.tick();
-            bar.set_message(&format!("Copied {}", HumanBytes(process_info.total_bytes)));
-            fs_extra::dir::TransitProcessResult::ContinueOrAbort
+            bar.set_message(&format!("Copied {}", HumanBytes(process_info.copied_bytes)));
+            TransitProcessResult::ContinueOrAbort
         })
         .context("Could not copy files to mod dir")?;
         bar.finish_with_message("Finished copying files to mod dir");
-
-        fs::rename(&mods_path.join(old_name), &target_path)?;
     } else {
         // move
-        fs::rename(&mod_path, &target_path).context("Could not move mod to mods")?;
+        fs::rename(&mod_path, &install_path).context("Could not move mod to mods")?;
     }
 
     if matches.is_present("rename files") {
-        lowercase(&target_path, true).context("Could not rename files to lowercase")?;
+        let renames =
+            lowercase(&install_path, false).context("Could not rename files to lowercase")?;
+        debug!("Renamed {} files to lowercase", renames.len());
+    }
+
+    chown(&install_path, &user, true).context(InstallError::Chown)?;
+    chmod(&install_path, 0o755, 0o644, true).context(InstallError::Chmod)?;
+
+    if update {
+        let previous_path = mods_path.join(format!("{}{}", name, PREVIOUS_SUFFIX));
+
+        replace_path(&install_path, &target_path, &previous_path)
+            .context("Could not replace old mod")?;
+
+        Term::stdout().write_line(&format!(
+            "Kept previous version of {} as {}",
+            &name,
+            previous_path.display()
+        ))?;
     }
 
-    chown(&target_path, &user, true).context(InstallError::Chown)?;
-    chmod(&target_path, 0o755, 0o644, true).context(InstallError::Chmod)?;
+    install_keys(&target_path, Path::new(&server_path), &user)?;
+
+    AddonIndex::refresh_mod(&server_path, &name).context("Could not update addon index")?;
+
+    let retention = settings
+        .get_int("mods.keep_previous_days")
+        .context("Could not get mods.keep_previous_days from config")?
+        .unwrap_or(DEFAULT_KEEP_PREVIOUS_DAYS);
+
+    purge_previous(
+        &mods_path,
+        Duration::from_secs(retention.max(0) as u64 * 24 * 60 * 60),
+    )
+    .context("Could not remove expired previous versions")?;
+
+    if let Some(staging) = staging_path {
+        staging.remove()?;
+    }
 
     Term::stdout().write_line(&format!("Sucessfully installed {}", &name))?;
 
     Ok(())
 }
+
+/// The directory an archive is extracted into, which is removed when the install finishes or
+/// fails.
+struct Staging(PathBuf);
+
+impl Staging {
+    fn remove(self) -> Result<()> {
+        // the staging directory itself was moved if the archive had no `@ModName` directory
+        if self.0.exists() {
+            fs::remove_dir_all(&self.0).context("Could not remove staging directory")?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if self.0.exists() {
+            if let Err(err) = fs::remove_dir_all(&self.0) {
+                debug!("Could not remove staging directory: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Copy the BattlEye keys (`*.bikey`) shipped with a mod into the servers `keys` directory.
+fn install_keys(mod_path: &Path, server_path: &Path, user: &str) -> Result<()> {
+    let keys_path = server_path.join("keys");
+
+    let keys = WalkDir::new(mod_path)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .and_then(OsStr::to_str)
+                .map(|ext| ext.eq_ignore_ascii_case("bikey"))
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    if !keys_path.exists() {
+        fs::create_dir_all(&keys_path).context("Could not create keys directory")?;
+        chown(&keys_path, user, false).context(InstallError::Chown)?;
+    }
+
+    for key in keys {
+        let target_path = keys_path.join(key.file_name().to_string_lossy().to_lowercase());
+
+        debug!(
+            "Copying key {} to {}",
+            key.path().display(),
+            target_path.display()
+        );
+
+        fs::copy(key.path(), &target_path).context("Could not copy key")?;
+        chown(&target_path, user, false).context(InstallError::Chown)?;
+        chmod(&target_path, 0o755, 0o644, false).context(InstallError::Chmod)?;
+    }
+
+    Ok(())
+}
+
+/// Remove previous versions of mods that were replaced longer than `retention` ago.
+fn purge_previous(mods_path: &Path, retention: Duration) -> Result<()> {
+    let now = SystemTime::now();
+
+    for previous_path in list_previous(mods_path)? {
+        let replaced = fs::metadata(&previous_path)
+            .and_then(|m| m.modified())
+            .context("Could not get modification time")?;
+
+        if now.duration_since(replaced).unwrap_or_default() >= retention {
+            debug!("Removing expired {}", previous_path.display());
+
+            fs::remove_dir_all(&previous_path)
+                .with_context(|| format!("Could not remove {}", previous_path.display()))?;
+        }
+    }
+
+    Ok(())
+}
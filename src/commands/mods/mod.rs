@@ -3,7 +3,14 @@ use crate::commands::prelude::*;
 pub fn cli() -> App {
     SubCommand::with_name("mods")
         .about("Manage mods")
-        .subcommands(vec![install::cli(), fix::cli(), list::cli()])
+        .subcommands(vec![
+            install::cli(),
+            fix::cli(),
+            list::cli(),
+            provides::cli(),
+            requires::cli(),
+            rollback::cli(),
+        ])
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
@@ -13,6 +20,9 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
         "fix" => fix::exec,
         "install" => install::exec,
         "list" => list::exec,
+        "provides" => provides::exec,
+        "requires" => requires::exec,
+        "rollback" => rollback::exec,
         _ => {
             cli().print_help()?;
             return Ok(());
@@ -25,3 +35,6 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
 pub mod fix;
 pub mod install;
 pub mod list;
+pub mod provides;
+pub mod requires;
+pub mod rollback;
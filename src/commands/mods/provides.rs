@@ -0,0 +1,57 @@
+use crate::commands::prelude::*;
+use amraam::{
+    addons::{AddonIndex, Provider},
+    Settings,
+};
+use console::Term;
+
+pub fn cli() -> App {
+    SubCommand::with_name("provides")
+        .about("Find the mods that provide an addon")
+        .long_about(
+            "Look up which installed mods or whether the base game contain the CfgPatches class \
+            of an addon, e.g. `ace_main`.",
+        )
+        .args(&[
+            Arg::with_name("addon")
+                .required(true)
+                .takes_value(true)
+                .help("The name of the CfgPatches class"),
+            Arg::with_name("refresh")
+                .short("r")
+                .long("refresh")
+                .help("Rescan all installed mods before searching"),
+        ])
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+    let term = Term::buffered_stdout();
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let addon = matches
+        .value_of("addon")
+        .context("Could not get addon name from arguments")?;
+
+    let index = AddonIndex::load_or_build(&server_path, matches.is_present("refresh"))
+        .context("Could not load addon index")?;
+
+    let providers = index.providers(addon);
+    ensure!(!providers.is_empty(), "No installed mod provides {}", addon);
+
+    for provider in providers {
+        term.write_line(&match provider {
+            Provider::BaseGame => " base game".to_owned(),
+            Provider::Mod(name) => format!(" {}", name),
+        })
+        .context("Could not write line on terminal")?;
+    }
+
+    term.flush().context("Could not flush terminal")?;
+
+    Ok(())
+}
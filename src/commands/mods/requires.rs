@@ -0,0 +1,83 @@
+use crate::commands::prelude::*;
+use amraam::{
+    addons::{AddonIndex, Provider},
+    Settings,
+};
+use console::{style, Term};
+
+pub fn cli() -> App {
+    SubCommand::with_name("requires")
+        .about("List the addons a mod depends on")
+        .long_about(
+            "List the requiredAddons of all PBOs of a mod that the mod does not provide itself \
+            together with the installed mods that provide them.",
+        )
+        .args(&[
+            Arg::with_name("name")
+                .required(true)
+                .takes_value(true)
+                .help("The name of the mod"),
+            Arg::with_name("refresh")
+                .short("r")
+                .long("refresh")
+                .help("Rescan all installed mods first"),
+        ])
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+    let term = Term::buffered_stdout();
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let mod_name = matches
+        .value_of("name")
+        .context("Could not get mod name from arguments")?;
+
+    let index = AddonIndex::load_or_build(&server_path, matches.is_present("refresh"))
+        .context("Could not load addon index")?;
+
+    let requirements = index
+        .requirements(mod_name)
+        .with_context(|| format!("Mod {} is not installed", mod_name))?;
+
+    term.write_line(&format!("Addons required by {}:", mod_name))
+        .context("Could not write header line on terminal")?;
+
+    let mut missing = 0;
+
+    for addon in &requirements {
+        let providers = index
+            .providers(addon)
+            .into_iter()
+            .map(|provider| match provider {
+                Provider::BaseGame => "base game",
+                Provider::Mod(name) => name,
+            })
+            .collect::<Vec<&str>>();
+
+        let providers = if providers.is_empty() {
+            missing += 1;
+            style("missing".to_owned()).red()
+        } else {
+            style(providers.join(", ")).green()
+        };
+
+        term.write_line(&format!(" {:<32} {}", addon, providers))
+            .context("Could not write line on terminal")?;
+    }
+
+    term.flush().context("Could not flush terminal")?;
+
+    ensure!(
+        missing == 0,
+        "{} addons required by {} are not provided by any installed mod",
+        missing,
+        mod_name
+    );
+
+    Ok(())
+}
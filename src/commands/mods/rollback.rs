@@ -0,0 +1,80 @@
+use crate::commands::prelude::*;
+use amraam::{
+    addons::AddonIndex,
+    tools::replace_path,
+    util::{PREVIOUS_SUFFIX, STAGED_SUFFIX},
+    Settings,
+};
+use console::Term;
+use std::{fs, path::Path};
+
+pub fn cli() -> App {
+    SubCommand::with_name("rollback")
+        .about("Roll back a mod to its previous version")
+        .long_about(
+            "Replace a mod with the version kept by the last `mods install --update`. The \
+            replaced version is kept in turn, so running rollback again undoes the rollback.",
+        )
+        .arg(
+            Arg::with_name("name")
+                .required(true)
+                .takes_value(true)
+                .help("The name of the mod"),
+        )
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    match sudo::escalate_if_needed() {
+        Ok(_) => {}
+        Err(err) => bail!("Could not escalate with sudo: {}", err),
+    };
+
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let mods_path = Path::new(&server_path).join("mods");
+
+    let mod_name = matches
+        .value_of("name")
+        .context("Could not get mod name from arguments")?;
+
+    let target_path = mods_path.join(mod_name);
+    let previous_path = mods_path.join(format!("{}{}", mod_name, PREVIOUS_SUFFIX));
+    let staged_path = mods_path.join(format!("{}{}", mod_name, STAGED_SUFFIX));
+
+    ensure!(
+        previous_path.is_dir(),
+        "There is no previous version of {} to roll back to",
+        mod_name
+    );
+
+    if staged_path.exists() {
+        fs::remove_dir_all(&staged_path).context("Could not remove old staged mod")?;
+    }
+
+    // an update interrupted between moving the mod aside and moving the new version into place
+    // leaves only the previous version
+    if !target_path.exists() {
+        Term::stdout().write_line(&format!(
+            "{} is missing, restoring its previous version",
+            mod_name
+        ))?;
+    }
+
+    fs::rename(&previous_path, &staged_path).context("Could not stage previous version")?;
+
+    if let Err(err) = replace_path(&staged_path, &target_path, &previous_path) {
+        fs::rename(&staged_path, &previous_path).context("Could not restore previous version")?;
+        return Err(err).context("Could not roll back mod");
+    }
+
+    AddonIndex::refresh_mod(&server_path, mod_name).context("Could not update addon index")?;
+
+    Term::stdout().write_line(&format!("Sucessfully rolled back {}", mod_name))?;
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use super::{start, stop};
+use crate::commands::prelude::*;
+
+pub fn cli() -> App {
+    SubCommand::with_name("restart")
+        .about("Restart a server in the background")
+        .args(&[
+            Arg::with_name("option set").takes_value(true),
+            Arg::with_name("timeout")
+                .short("t")
+                .long("timeout")
+                .takes_value(true)
+                .help("Seconds to wait before killing the server"),
+        ])
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let instance =
+        settings.get_instance(matches.value_of("instance"), matches.value_of("option set"))?;
+
+    stop::stop(&server_path, &instance.name, stop::timeout(matches)?)?;
+    start::start(&settings, matches)
+}
@@ -1,14 +1,28 @@
 use crate::commands::prelude::*;
 use amraam::{
-    config::{
-        modpack::{Mod, Modpack, ModpackConfig},
-        OptionSet,
-    },
-    util::list_mods,
+    addons::AddonIndex,
+    config::modpack::Mod,
+    config::OptionSet,
+    daemon::PidFile,
+    logs::{self, LogPolicy, OutputLog},
+    ports,
+    settings::Instance,
+    supervisor::{self, RestartPolicy, Schedule, Supervisor},
     Settings,
 };
-use anyhow::{bail, ensure, Context, Result};
-use std::{convert::TryInto, path::Path, process::Command};
+use anyhow::{ensure, Context, Result};
+use console::{style, Term};
+use nix::unistd::{Uid, User};
+use std::{
+    convert::TryInto,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process::{Child, Command, Stdio},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
 
 macro_rules! arg {
     ($command:expr, $option:expr, $arg:expr) => {
@@ -34,59 +48,349 @@ macro_rules! arg_bool {
 pub fn cli() -> App {
     SubCommand::with_name("run")
         .about("Runs the current arma installation")
-        .arg(Arg::with_name("option set").takes_value(true))
+        .args(&[
+            Arg::with_name("option set").takes_value(true),
+            Arg::with_name("supervise")
+                .short("s")
+                .long("supervise")
+                .help("Restart the server when it crashes and at the times in supervisor.restarts"),
+            Arg::with_name("dry run")
+                .short("n")
+                .long("dry-run")
+                .conflicts_with("supervise")
+                .help("Print the command that would be executed instead of starting the server"),
+        ])
+        .args(&option_args())
+}
+
+/// Arguments overriding the options of the instance or option set.
+pub fn option_args() -> Vec<Arg<'static, 'static>> {
+    let mut args = [
+        ("binary", "Server binary, like arma3server_x64"),
+        (
+            "port",
+            "Game port, the server also uses the next four ports",
+        ),
+        ("ranking", "File to write the player rankings to"),
+        (
+            "bandwidth-alg",
+            "Bandwidth algorithm, 2 is the improved one",
+        ),
+        ("cpu-count", "Number of cores to use, -1 for all"),
+        (
+            "ex-threads",
+            "Extra threads for file operations, geometry and textures",
+        ),
+        ("basic", "Basic config to use without the .cfg extension"),
+        (
+            "server-config",
+            "Server config to use from the [config] tables",
+        ),
+        ("limit-fps", "Frame limit of the server, from 5 to 1000"),
+        ("malloc", "Memory allocator to use, like system"),
+        ("max-mem", "Memory limit in MB"),
+        ("max-vram", "Video memory limit in MB"),
+        ("world", "World to load on startup, like empty"),
+        ("bepath", "Directory of BattlEye"),
+        ("ip", "Address to bind to"),
+        ("profile", "Name of the profile"),
+        ("profiles", "Directory for profiles and logs"),
+        ("modpack", "Modpack to load with -mod"),
+        ("server-modpack", "Modpack to load with -serverMod"),
+    ]
+    .iter()
+    .map(|&(name, help)| {
+        Arg::with_name(name)
+            .long(name)
+            .takes_value(true)
+            .allow_hyphen_values(name == "cpu-count")
+            .help(help)
+    })
+    .collect::<Vec<_>>();
+
+    for &(name, negated, help) in &[
+        (
+            "load-mission-to-memory",
+            "no-load-mission-to-memory",
+            "Load the mission into memory once",
+        ),
+        ("enable-ht", "no-enable-ht", "Use hyper-threading cores"),
+        ("hugepages", "no-hugepages", "Use huge pages"),
+        (
+            "auto-init",
+            "no-auto-init",
+            "Start the mission without players",
+        ),
+        ("no-sound", "sound", "Disable sound output"),
+        (
+            "file-patching",
+            "no-file-patching",
+            "Allow loading unpacked files",
+        ),
+        ("netlog", "no-netlog", "Log network traffic"),
+        (
+            "debug-call-extension",
+            "no-debug-call-extension",
+            "Log calls to extensions",
+        ),
+        ("no-logs", "logs", "Do not write logs"),
+    ] {
+        args.push(Arg::with_name(name).long(name).help(help));
+        args.push(
+            Arg::with_name(negated)
+                .long(negated)
+                .conflicts_with(name)
+                .help("Do not pass the flag even if the option set sets it"),
+        );
+    }
+
+    args.push(
+        Arg::with_name("extra-arg")
+            .long("extra-arg")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .allow_hyphen_values(true)
+            .help("Pass an argument to the server as it is, like -limitFPS=50"),
+    );
+
+    args
+}
+
+/// Get the options set with the arguments of [`option_args`].
+pub fn overrides(matches: &ArgMatches) -> Result<OptionSet> {
+    Ok(OptionSet {
+        binary: value(matches, "binary"),
+        port: parse(matches, "port")?,
+        ranking: value(matches, "ranking"),
+        load_mission_to_memory: flag(
+            matches,
+            "load-mission-to-memory",
+            "no-load-mission-to-memory",
+        ),
+        bandwidth_algorithm: parse(matches, "bandwidth-alg")?,
+        cpu_count: parse(matches, "cpu-count")?,
+        ex_threads: parse(matches, "ex-threads")?,
+        enable_ht: flag(matches, "enable-ht", "no-enable-ht"),
+        hugepages: flag(matches, "hugepages", "no-hugepages"),
+        auto_init: flag(matches, "auto-init", "no-auto-init"),
+        limit_fps: parse(matches, "limit-fps")?,
+        malloc: value(matches, "malloc"),
+        max_mem: parse(matches, "max-mem")?,
+        max_vram: parse(matches, "max-vram")?,
+        no_sound: flag(matches, "no-sound", "sound"),
+        world: value(matches, "world"),
+        bepath: value(matches, "bepath"),
+        file_patching: flag(matches, "file-patching", "no-file-patching"),
+        netlog: flag(matches, "netlog", "no-netlog"),
+        debug_call_extension: flag(matches, "debug-call-extension", "no-debug-call-extension"),
+        no_logs: flag(matches, "no-logs", "logs"),
+        ip: value(matches, "ip"),
+
+        basic: value(matches, "basic"),
+        config: value(matches, "server-config"),
+        profile: value(matches, "profile"),
+        profiles: value(matches, "profiles"),
+        modpack: value(matches, "modpack"),
+        server_modpack: value(matches, "server-modpack"),
+        extra_args: matches
+            .values_of("extra-arg")
+            .map(|args| args.map(str::to_owned).collect()),
+    })
+}
+
+fn value(matches: &ArgMatches, name: &str) -> Option<String> {
+    matches.value_of(name).map(str::to_owned)
+}
+
+fn parse<T>(matches: &ArgMatches, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    matches
+        .value_of(name)
+        .map(|value| {
+            value
+                .parse()
+                .with_context(|| format!("Invalid value {} for --{}", value, name))
+        })
+        .transpose()
+}
+
+fn flag(matches: &ArgMatches, name: &str, negated: &str) -> Option<bool> {
+    if matches.is_present(name) {
+        Some(true)
+    } else if matches.is_present(negated) {
+        Some(false)
+    } else {
+        None
+    }
 }
 
 pub fn exec(matches: &ArgMatches) -> Result<()> {
     let settings =
         Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
 
-    let mut options = OptionSet::new();
-
-    if let Some(globals) = settings
-        .get::<OptionSet>("options.global")
-        .context("Could not get global options")?
-    {
-        options.merge(globals)
+    let dry_run = matches.is_present("dry run");
+    let launch = launch(&settings, matches, dry_run)?;
+    if dry_run {
+        return print(&settings, &launch);
     }
 
-    if let Some(options_name) = matches.value_of("option set") {
-        if let Some(set) = settings
-            .get(&format!("options.{}", options_name))
-            .context("Could not get provided option set")?
-        {
-            options.merge(set)
-        } else {
-            bail!("Could nof find option set in config")
+    let Launch {
+        instance,
+        server_path,
+        mut command,
+        ..
+    } = launch;
+
+    let pid_file = PidFile::acquire(&server_path, &instance.name)?;
+
+    // checked after acquiring the PID file, a running instance would hold its own ports
+    ports::check(&settings, &instance)?;
+
+    pid_file.set_command(command_line(&command))?;
+
+    let log_policy = LogPolicy::from_settings(&settings)?;
+    let log_dir = logs::log_dir(&server_path, &instance.options);
+    let output = match &log_dir {
+        Some(dir) if log_policy.capture => {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+            // the output stays visible when running in a terminal
+            Some(Arc::new(Mutex::new(OutputLog::new(
+                dir,
+                log_policy.clone(),
+                console::user_attended(),
+            ))))
+        }
+        _ => None,
+    };
+
+    let mut spawn = || -> Result<(Child, Vec<JoinHandle<()>>)> {
+        if let Some(output) = &output {
+            output
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .start()
+                .context("Could not rotate logs")?;
+        } else if let Some(dir) = &log_dir {
+            // the RPT logs of the server are rotated even if its output is not captured
+            logs::rotate(dir, &log_policy, false).context("Could not rotate logs")?;
         }
+
+        let mut child = command.spawn().context("Could not execute arma3server")?;
+        let capture = match &output {
+            Some(output) => OutputLog::capture(output, &mut child),
+            None => Vec::new(),
+        };
+
+        Ok((child, capture))
+    };
+
+    if matches.is_present("supervise") {
+        let policy = RestartPolicy::from_settings(&settings, &instance)?;
+        let schedule = Schedule::from_settings(&settings, &instance)?;
+        return Supervisor::new(policy, schedule).run(|| Ok(spawn()?.0));
+    }
+
+    // the server is terminated with amraam, e.g. by `stop`, instead of being left behind
+    supervisor::handle_stop_signals()?;
+
+    let (mut child, capture) = spawn()?;
+    let status = supervisor::wait(&mut child)?;
+
+    for thread in capture {
+        let _ = thread.join();
+    }
+
+    if let Some(status) = status {
+        ensure!(status.success(), "Arma Server did not return sucessfully");
     }
 
+    Ok(())
+}
+
+/// The resolved command line of a server and what it was built from.
+pub struct Launch {
+    pub instance: Instance,
+    pub server_path: String,
+    pub command: Command,
+    /// The mods passed with `-mod=` in load order.
+    pub mods: Vec<Mod>,
+    /// The mods passed with `-serverMod=` in load order.
+    pub server_mods: Vec<Mod>,
+}
+
+/// Build the command line for the instance or option set selected in `matches`.
+///
+/// A `dry_run` only orders the mods by the stored addon index and never scans the game.
+pub fn launch(settings: &Settings, matches: &ArgMatches, dry_run: bool) -> Result<Launch> {
+    let instance = settings.get_instance_with(
+        matches.value_of("instance"),
+        matches.value_of("option set"),
+        overrides(matches)?,
+    )?;
+
     let server_path = settings
         .get_server_path()
         .context("Could not get server path from config")?;
 
-    // allow changing
-    let server_binary = "./arma3serverprofiling_x64";
+    if let Err(err) = instance.options.validate() {
+        let source = instance
+            .sources
+            .get(err.option())
+            .map(String::as_str)
+            .unwrap_or("default");
+
+        return Err(err).context(format!("Invalid option in {}", source));
+    }
+
+    let options = instance.options.clone();
+
+    let binary = options
+        .binary
+        .context("No server binary set, set server.branch or server.binary")?;
+    // a plain file name has to be run from the working directory
+    let server_binary = Path::new(".").join(&binary);
+
+    let binary_path = Path::new(&server_path).join(&binary);
+    let metadata = fs::metadata(&binary_path)
+        .with_context(|| format!("Server binary {} does not exist", binary_path.display()))?;
+    ensure!(
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        "Server binary {} is not executable",
+        binary_path.display()
+    );
 
     let arma_user = settings
         .get_str("server.user")
         .context("Could not get server user")?
         .context("Missing server.user key in config")?;
 
-    let mut command = Command::new("sudo");
+    // under systemd amraam already runs as the server user
+    let current_user = User::from_uid(Uid::current())
+        .context("Could not get current user")?
+        .map(|user| user.name);
+
+    let mut command = if current_user.as_deref() == Some(arma_user.as_str()) {
+        Command::new(&server_binary)
+    } else {
+        let mut command = Command::new("sudo");
+        command.args(["-u", &arma_user]).arg(&server_binary);
+        command
+    };
     command.current_dir(&server_path);
-    command.args(&["-u", &arma_user, &server_binary]);
 
     if let Some(name) = options.config {
-        let config = settings
-            .get_str(&format!("config.{}.path", name))
-            .context("Could not get config file path from config")?
-            .unwrap_or_else(|| name);
+        let config = settings.get_server_config(&name)?;
         command.arg(&format!("-config={}.cfg", config));
     }
 
     arg!(command, options.basic, "cfg", ".cfg");
     arg!(command, options.port, "port");
+    arg!(command, options.profile, "name");
+    arg!(command, options.profiles, "profiles");
     arg!(command, options.ranking, "ranking");
     arg_bool!(
         command,
@@ -99,66 +403,183 @@ pub fn exec(matches: &ArgMatches) -> Result<()> {
     arg_bool!(command, options.enable_ht, "enableHT");
     arg_bool!(command, options.hugepages, "hugepages");
     arg_bool!(command, options.auto_init, "autoInit");
+    arg!(command, options.limit_fps, "limitFPS");
+    arg!(command, options.malloc, "malloc");
+    arg!(command, options.max_mem, "maxMem");
+    arg!(command, options.max_vram, "maxVRAM");
+    arg_bool!(command, options.no_sound, "noSound");
+    arg!(command, options.world, "world");
+    arg!(command, options.bepath, "bepath");
+    arg_bool!(command, options.file_patching, "filePatching");
+    arg_bool!(command, options.netlog, "netlog");
+    arg_bool!(command, options.debug_call_extension, "debugCallExtension");
+    arg_bool!(command, options.no_logs, "noLogs");
+    arg!(command, options.ip, "ip");
 
-    let mut mods = Vec::new();
+    let mods = match options.modpack {
+        Some(name) => settings.get_modpack_mods(&name, &server_path)?,
+        None => Vec::new(),
+    };
+    let server_mods = match options.server_modpack {
+        Some(name) => settings.get_modpack_mods(&name, &server_path)?,
+        None => Vec::new(),
+    };
+
+    // server mods can depend on client mods and the other way round, so they are resolved together
+    let mut server_paths = server_mods
+        .iter()
+        .map(|m| m.path.clone())
+        .collect::<Vec<String>>();
+    let (server_mods, mods): (Vec<Mod>, Vec<Mod>) = resolve_dependencies(
+        &server_path,
+        mods.into_iter().chain(server_mods).collect(),
+        dry_run,
+    )?
+    .into_iter()
+    .partition(|m| match server_paths.iter().position(|p| p == &m.path) {
+        // a mod can be in both lists
+        Some(i) => {
+            server_paths.remove(i);
+            true
+        }
+        None => false,
+    });
 
-    if let Some(modpack_name) = options.modpack {
-        let mut modpack = load_modpack(&settings, &modpack_name, &server_path)?;
-        mods.append(&mut modpack.mods);
+    command.arg(format!("-mod={}", join_paths(&mods)));
+    if !server_mods.is_empty() {
+        command.arg(format!("-serverMod={}", join_paths(&server_mods)));
+    }
+
+    if let Some(extra_args) = options.extra_args {
+        command.args(extra_args);
+    }
 
-        for name in modpack.inherit {
-            let mut modpack = load_modpack(&settings, &name, &server_path)?;
-            mods.append(&mut modpack.mods);
+    Ok(Launch {
+        instance,
+        server_path,
+        command,
+        mods,
+        server_mods,
+    })
+}
+
+/// Print the options of a launch with the option sets they came from, the mods in load order and
+/// the command line.
+pub fn print(settings: &Settings, launch: &Launch) -> Result<()> {
+    let term = Term::buffered_stdout();
+    let options =
+        serde_json::to_value(&launch.instance.options).context("Could not serialize option set")?;
+
+    term.write_line(&format!(
+        "{} {}",
+        style("Instance").bold(),
+        launch.instance.name
+    ))?;
+
+    term.write_line(&format!("\n{}", style("Options").bold()))?;
+    for (field, source) in &launch.instance.sources {
+        let value = match &options[field] {
+            serde_json::Value::String(name) if field == "config" => {
+                format!("{} ({}.cfg)", name, settings.get_server_config(name)?)
+            }
+            serde_json::Value::String(value) => value.clone(),
+            serde_json::Value::Array(values) => values
+                .iter()
+                .filter_map(serde_json::Value::as_str)
+                .collect::<Vec<&str>>()
+                .join(" "),
+            value => value.to_string(),
+        };
+
+        term.write_line(&format!(
+            "  {:<24} {:<32} {}",
+            field,
+            value,
+            style(source).dim()
+        ))?;
+    }
+
+    for (title, mods) in &[("Mods", &launch.mods), ("Server mods", &launch.server_mods)] {
+        if mods.is_empty() {
+            continue;
+        }
+
+        term.write_line(&format!("\n{}", style(title).bold()))?;
+        for (i, m) in mods.iter().enumerate() {
+            term.write_line(&format!("  {:>3}. {:<40} {}", i + 1, m.name, m.path))?;
         }
     }
 
-    command.arg(format!(
-        "-mod={}",
-        mods.iter()
-            .map(|m| m.path.clone())
-            .collect::<Vec<String>>()
-            .join(";")
-    ));
+    term.write_line(&format!(
+        "\n{} (in {})",
+        style("Command").bold(),
+        Path::new(&launch.server_path).display()
+    ))?;
+    term.write_line(&format!("  {}", command_line(&launch.command).join(" ")))?;
 
-    ensure!(
-        command
-            .status()
-            .context("Could not execute arma3server")?
-            .success(),
-        "Arma Server did not return sucessfully"
-    );
+    term.flush().context("Could not flush terminal")?;
 
     Ok(())
 }
 
-fn load_modpack(settings: &Settings, name: &str, server_path: &str) -> Result<Modpack> {
-    let modpack_config: ModpackConfig = settings
-        .get(&format!("modpack.{}", name))
-        .context("Could not get modpack from config")?
-        .context("Missing modpack config entry")?;
-
-    let modpack: Modpack = modpack_config.as_modpack()?;
+fn command_line(command: &Command) -> Vec<String> {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
 
-    let mods_path = Path::new(&server_path).join("mods");
-    let installed_mods = list_mods(&mods_path)?;
+fn join_paths(mods: &[Mod]) -> String {
+    mods.iter()
+        .map(|m| m.path.clone())
+        .collect::<Vec<String>>()
+        .join(";")
+}
 
-    let missing_mods: Vec<Mod> = modpack
-        .mods
+/// Warn about addons the mods require but do not provide each other and order them so
+/// dependencies are loaded first.
+///
+/// Addons whose config can not be read are indexed by their file name, so missing dependencies
+/// are not fatal. A `dry_run` uses the stored index as it is and leaves the mods in config order
+/// if there is none.
+fn resolve_dependencies(server_path: &str, mods: Vec<Mod>, dry_run: bool) -> Result<Vec<Mod>> {
+    let names = mods
         .iter()
-        .filter(|&m| !installed_mods.contains(&m.name))
-        .map(|m| m.clone())
-        .collect();
-
-    if !missing_mods.is_empty() {
-        bail!(
-            "Cannot run server since some mods are missing:\n{}",
-            missing_mods
+        .map(Mod::dir_name)
+        .collect::<Result<Vec<String>>>()?;
+    let names = names.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let index = if dry_run {
+        match AddonIndex::load(server_path).context("Could not load addon index")? {
+            Some(index) => index,
+            None => {
+                debug!("No addon index, mods are not ordered by their dependencies");
+                return Ok(mods);
+            }
+        }
+    } else {
+        let mut index =
+            AddonIndex::load_or_build(server_path, false).context("Could not load addon index")?;
+        index.update_stale(server_path, &names)?;
+        index
+    };
+
+    let missing = index.missing_dependencies(&names);
+    if !missing.is_empty() {
+        Term::stderr().write_line(&format!(
+            "{}: Some mods are missing dependencies:\n{}",
+            style("Warning").yellow(),
+            missing
                 .iter()
-                .map(|m| m.name.clone())
+                .map(ToString::to_string)
                 .collect::<Vec<String>>()
                 .join("\n")
-        )
+        ))?;
     }
 
-    Ok(modpack)
+    let order = index.load_order(&names);
+    let mut mods = mods.into_iter().zip(names).collect::<Vec<(Mod, &str)>>();
+    mods.sort_by_key(|(_, name)| order.iter().position(|n| n == name));
+
+    Ok(mods.into_iter().map(|(m, _)| m).collect())
 }
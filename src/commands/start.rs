@@ -0,0 +1,120 @@
+use crate::commands::prelude::*;
+use amraam::{
+    daemon::{self, run_dir},
+    ports,
+};
+use console::Term;
+use nix::unistd::setsid;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io,
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for the server to come up before leaving it to start in the background.
+const START_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn cli() -> App {
+    SubCommand::with_name("start")
+        .about("Start the server in the background")
+        .long_about(
+            "Run the server with `run --supervise` in the background. The output is written to \
+            <server>/.run/<instance or option set>.log.",
+        )
+        .arg(Arg::with_name("option set").takes_value(true))
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+
+    start(&settings, matches)
+}
+
+/// Start `amraam run --supervise` for the instance or option set in a new session.
+pub fn start(settings: &Settings, matches: &ArgMatches) -> Result<()> {
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+    let server_path = server_path.as_str();
+
+    let option_set = matches.value_of("option set");
+    let instance = settings.get_instance(matches.value_of("instance"), option_set)?;
+    let name = instance.name.as_str();
+
+    ensure!(
+        daemon::status(server_path, name)?.is_none(),
+        "Server {} is already running",
+        name
+    );
+
+    ports::check(settings, &instance)?;
+
+    fs::create_dir_all(run_dir(server_path)).context("Could not create run directory")?;
+    let log_path = run_dir(server_path).join(format!("{}.log", name));
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Could not open log file {}", log_path.display()))?;
+
+    let mut command = Command::new(env::current_exe().context("Could not get executable")?);
+    if let Some(config) = matches.value_of("config") {
+        command.args(["--config", config]);
+    }
+    if let Some(instance) = matches.value_of("instance") {
+        command.args(["--instance", instance]);
+    }
+
+    command
+        .arg("run")
+        .args(option_set)
+        .arg("--supervise")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().context("Could not open log file")?)
+        .stderr(log);
+
+    // detach from the terminal so the server survives the end of the session
+    unsafe {
+        command.pre_exec(|| {
+            setsid().map_err(io::Error::other)?;
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn().context("Could not start server")?;
+    let pid = child.id() as i32;
+
+    let started = Instant::now();
+    while started.elapsed() < START_TIMEOUT {
+        if let Some(status) = child.try_wait().context("Could not wait for server")? {
+            bail!(
+                "Server exited with {}, see {} for details",
+                status,
+                log_path.display()
+            );
+        }
+
+        match daemon::status(server_path, name)? {
+            Some(state) if state.pid == pid && !state.command.is_empty() => {
+                Term::stdout()
+                    .write_line(&format!("Sucessfully started {} with PID {}", name, pid))?;
+                return Ok(());
+            }
+            _ => thread::sleep(Duration::from_millis(200)),
+        }
+    }
+
+    Term::stdout().write_line(&format!(
+        "Server {} with PID {} is still starting, see {}",
+        name,
+        pid,
+        log_path.display()
+    ))?;
+
+    Ok(())
+}
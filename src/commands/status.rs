@@ -0,0 +1,76 @@
+use crate::commands::prelude::*;
+use amraam::daemon::{self, DEFAULT_NAME};
+use chrono::Utc;
+use console::{style, Term};
+use indicatif::HumanDuration;
+use std::time::Duration;
+
+pub fn cli() -> App {
+    SubCommand::with_name("status")
+        .about("Show which servers are running")
+        .arg(
+            Arg::with_name("option set")
+                .takes_value(true)
+                .help("Only show the server of this option set"),
+        )
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+    let term = Term::buffered_stdout();
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let names = match (matches.value_of("instance"), matches.value_of("option set")) {
+        (None, None) => {
+            let mut names = settings
+                .get_table("options")
+                .context("Could not get option sets from config")?
+                .unwrap_or_default()
+                .into_keys()
+                .filter(|name| name != DEFAULT_NAME)
+                .collect::<Vec<String>>();
+            names.sort();
+            names.insert(0, DEFAULT_NAME.to_owned());
+
+            for name in settings.get_instance_names()? {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+
+            names
+        }
+        (instance, option_set) => vec![settings.get_instance(instance, option_set)?.name],
+    };
+
+    for name in names {
+        match daemon::status(&server_path, &name)? {
+            Some(state) => {
+                let uptime =
+                    Duration::from_secs((Utc::now().timestamp() - state.started).max(0) as u64);
+
+                term.write_line(&format!(
+                    " {:<16} {} pid {} for {}",
+                    name,
+                    style("running").green(),
+                    state.pid,
+                    HumanDuration(uptime)
+                ))?;
+                if !state.command.is_empty() {
+                    term.write_line(&format!("   {}", state.command.join(" ")))?;
+                }
+            }
+            None => {
+                term.write_line(&format!(" {:<16} {}", name, style("stopped").red()))?;
+            }
+        }
+    }
+
+    term.flush().context("Could not flush terminal")?;
+
+    Ok(())
+}
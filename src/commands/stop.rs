@@ -0,0 +1,114 @@
+use crate::commands::prelude::*;
+use amraam::daemon;
+use console::Term;
+use nix::{
+    sys::signal::{kill, killpg, Signal},
+    unistd::Pid,
+};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Arma needs some time to save and disconnect players before it exits.
+const DEFAULT_TIMEOUT: &str = "60";
+
+/// How long a killed server may take to exit.
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn cli() -> App {
+    SubCommand::with_name("stop")
+        .about("Stop a server started in the background")
+        .long_about("Send SIGTERM to the server and SIGKILL if it did not stop within the timeout.")
+        .args(&[
+            Arg::with_name("option set").takes_value(true),
+            Arg::with_name("timeout")
+                .short("t")
+                .long("timeout")
+                .takes_value(true)
+                .default_value(DEFAULT_TIMEOUT)
+                .help("Seconds to wait before killing the server"),
+        ])
+}
+
+pub fn exec(matches: &ArgMatches) -> Result<()> {
+    let settings =
+        Settings::from_path(matches.value_of("config")).context("Could not load settings")?;
+
+    let server_path = settings
+        .get_server_path()
+        .context("Could not get server path from config")?;
+
+    let instance =
+        settings.get_instance(matches.value_of("instance"), matches.value_of("option set"))?;
+    let name = instance.name.as_str();
+
+    ensure!(
+        daemon::status(&server_path, name)?.is_some(),
+        "Server {} is not running",
+        name
+    );
+
+    stop(&server_path, name, timeout(matches)?)
+}
+
+pub fn timeout(matches: &ArgMatches) -> Result<Duration> {
+    Ok(Duration::from_secs(
+        matches
+            .value_of("timeout")
+            .unwrap_or(DEFAULT_TIMEOUT)
+            .parse()
+            .context("Timeout is not a number of seconds")?,
+    ))
+}
+
+/// Stop the server `name` if it is running.
+pub fn stop(server_path: &str, name: &str, timeout: Duration) -> Result<()> {
+    let term = Term::stdout();
+
+    let pid = match daemon::status(server_path, name)? {
+        Some(state) => Pid::from_raw(state.pid),
+        None => return Ok(()),
+    };
+
+    kill(pid, Signal::SIGTERM).context("Could not send SIGTERM to server")?;
+
+    if wait_for_exit(server_path, name, timeout)? {
+        term.write_line(&format!("Sucessfully stopped {}", name))?;
+        return Ok(());
+    }
+
+    term.write_line(&format!(
+        "Server {} did not stop within {} seconds, killing it",
+        name,
+        timeout.as_secs()
+    ))?;
+
+    // servers started with `start` lead their own process group which includes arma itself
+    if killpg(pid, Signal::SIGKILL).is_err() {
+        kill(pid, Signal::SIGKILL).context("Could not send SIGKILL to server")?;
+    }
+
+    // `restart` starts the server again right away, which needs the PID file to be released
+    ensure!(
+        wait_for_exit(server_path, name, KILL_TIMEOUT)?,
+        "Server {} did not exit after SIGKILL",
+        name
+    );
+
+    Ok(())
+}
+
+/// Wait until the server `name` released its PID file. Returns `false` on timeout.
+fn wait_for_exit(server_path: &str, name: &str, timeout: Duration) -> Result<bool> {
+    let started = Instant::now();
+
+    while started.elapsed() < timeout {
+        if daemon::status(server_path, name)?.is_none() {
+            return Ok(true);
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+
+    Ok(daemon::status(server_path, name)?.is_none())
+}
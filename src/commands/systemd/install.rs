@@ -0,0 +1,128 @@
+use crate::commands::prelude::*;
+use amraam::{config::SystemdUnit, supervisor::RestartPolicy};
+use console::Term;
+use std::{env, path::Path, process::Command};
+use thiserror::Error;
+
+/// Arma opens a lot of files with many mods loaded.
+const DEFAULT_LIMIT_NOFILE: i64 = 100_000;
+
+#[derive(Debug, Error)]
+pub enum InstallError {
+    #[error("Could not normalize path")]
+    Canonicalize,
+
+    #[error("Could not generate systemd unit")]
+    Generate,
+}
+
+pub fn cli() -> App {
+    SubCommand::with_name("install")
+        .about("Install a systemd unit for an instance or option set")
+        .long_about(
+            "Write the amraam@.service template unit and enable it for an instance or option \
+            set. The restart policy is taken from the supervisor table and the CPU affinity from \
+            the cpu_count of the option set.",
+        )
+        .args(&[
+            Arg::with_name("name")
+                .required_unless("instance")
+                .takes_value(true)
+                .help("The instance or option set to run as amraam@<name>.service"),
+            Arg::with_name("unit dir")
+                .long("unit-dir")
+                .takes_value(true)
+                .default_value("/etc/systemd/system")
+                .help("The directory the units are written to"),
+            Arg::with_name("no enable")
+                .long("no-enable")
+                .help("Only write the units without enabling them"),
+        ])
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let unit_dir = Path::new(
+        args.value_of("unit dir")
+            .context("Missing unit directory")?,
+    );
+    let enable = !args.is_present("no enable");
+
+    if enable {
+        match sudo::escalate_if_needed() {
+            Ok(_) => {}
+            Err(err) => bail!("Could not escalate with sudo: {}", err),
+        };
+    }
+
+    let config_path = Path::new(args.value_of("config").unwrap_or("amraam.toml"))
+        .canonicalize()
+        .context(InstallError::Canonicalize)
+        .context("Could not find config, pass its path with --config")?;
+
+    let settings = Settings::from_path(Some(&config_path)).context("Could not load settings")?;
+
+    let name = args
+        .value_of("instance")
+        .or_else(|| args.value_of("name"))
+        .context("Missing instance or option set")?;
+    let instance = settings.get_instance(Some(name), None)?;
+
+    let server_path = Path::new(
+        &settings
+            .get_server_path()
+            .context("Could not get server path from config")?,
+    )
+    .canonicalize()
+    .context(InstallError::Canonicalize)?;
+
+    let user = settings
+        .get_str("server.user")
+        .context("Could not get server user from config")?
+        .context("Missing config key server.user")?;
+
+    let policy = RestartPolicy::from_settings(&settings, &instance)?;
+
+    let unit = SystemdUnit {
+        user,
+        working_directory: server_path.display().to_string(),
+        executable: env::current_exe()
+            .context("Could not get executable")?
+            .display()
+            .to_string(),
+        config: config_path.display().to_string(),
+        restart_sec: policy.delay.as_secs(),
+        start_limit_interval: policy.window.as_secs(),
+        start_limit_burst: policy.max_restarts,
+        timeout_stop_sec: 120,
+        limit_nofile: settings
+            .get_int("systemd.limit_nofile")
+            .context("Could not get systemd.limit_nofile from config")?
+            .unwrap_or(DEFAULT_LIMIT_NOFILE)
+            .max(0) as u64,
+    };
+
+    unit.generate(unit_dir.join(SystemdUnit::NAME))
+        .context(InstallError::Generate)?;
+    SystemdUnit::generate_instance(unit_dir, name, instance.options.cpu_count)
+        .context(InstallError::Generate)?;
+
+    let instance = format!("amraam@{}.service", name);
+
+    if enable {
+        for args in &[&["daemon-reload"][..], &["enable", &instance][..]] {
+            ensure!(
+                Command::new("systemctl")
+                    .args(*args)
+                    .status()
+                    .context("Could not run systemctl")?
+                    .success(),
+                "systemctl {} failed",
+                args.join(" ")
+            );
+        }
+    }
+
+    Term::stdout().write_line(&format!("Sucessfully installed {}", instance))?;
+
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use crate::commands::prelude::*;
+
+pub fn cli() -> App {
+    SubCommand::with_name("systemd")
+        .about("Manage systemd units")
+        .subcommands(vec![install::cli()])
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let (cmd, sub_args) = args.subcommand();
+
+    let f = match cmd {
+        "install" => install::exec,
+        _ => {
+            cli().print_help()?;
+            return Ok(());
+        }
+    };
+
+    f(sub_args.context("Missing arguments")?)
+}
+
+pub mod install;
@@ -4,6 +4,7 @@ pub mod basic;
 pub mod modpack;
 pub mod profile;
 pub mod server;
+pub mod systemd;
 
 use serde_json::{json, Value};
 use tinytemplate::format_unescaped;
@@ -18,6 +19,7 @@ pub fn format(value: &Value, output: &mut String) -> tinytemplate::error::Result
 
 pub use basic::BasicConfig;
 pub use modpack::Modpack;
-pub use option_set::OptionSet;
+pub use option_set::{OptionError, OptionSet, OptionStack};
 pub use profile::Profile;
 pub use server::ServerConfig;
+pub use systemd::SystemdUnit;
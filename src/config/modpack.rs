@@ -1,6 +1,6 @@
 use anyhow::{ensure, Context, Result};
 use roxmltree::{Document, Node};
-use std::{fs::File, io::prelude::*};
+use std::{fs::File, io::prelude::*, path::Path};
 
 #[derive(Debug)]
 struct ModContainer<'a> {
@@ -16,6 +16,17 @@ pub struct Mod {
     pub id: Option<String>,
 }
 
+impl Mod {
+    /// The name of the directory of the mod, which is its name in the addon index.
+    pub fn dir_name(&self) -> Result<String> {
+        Ok(Path::new(&self.path)
+            .file_name()
+            .context("Mod path has no directory name")?
+            .to_string_lossy()
+            .into_owned())
+    }
+}
+
 #[derive(Deserialize, Clone)]
 pub struct ModpackConfig {
     inherit: Option<Vec<String>>,
@@ -1,5 +1,10 @@
-#[derive(Deserialize, Default)]
+use std::{collections::BTreeMap, net::IpAddr};
+use thiserror::Error;
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct OptionSet {
+    /// The server binary, relative to the server path.
+    pub binary: Option<String>,
     pub port: Option<u16>,
     pub ranking: Option<String>,
     pub load_mission_to_memory: Option<bool>,
@@ -9,17 +14,107 @@ pub struct OptionSet {
     pub enable_ht: Option<bool>,
     pub hugepages: Option<bool>,
     pub auto_init: Option<bool>,
+    pub limit_fps: Option<u16>,
+    /// The memory allocator to use, like `system`.
+    pub malloc: Option<String>,
+    /// Memory limit in MB.
+    pub max_mem: Option<u32>,
+    /// Video memory limit in MB.
+    pub max_vram: Option<u32>,
+    pub no_sound: Option<bool>,
+    /// The world loaded on startup, `empty` skips loading one.
+    pub world: Option<String>,
+    /// The BattlEye directory.
+    pub bepath: Option<String>,
+    pub file_patching: Option<bool>,
+    pub netlog: Option<bool>,
+    pub debug_call_extension: Option<bool>,
+    pub no_logs: Option<bool>,
+    /// The address to bind to.
+    pub ip: Option<String>,
 
     pub basic: Option<String>,
     pub config: Option<String>,
     pub profile: Option<String>,
+    /// The directory for profiles and logs, relative to the server path.
+    pub profiles: Option<String>,
     pub modpack: Option<String>,
     pub server_modpack: Option<String>,
+    /// Arguments passed to the server as they are, for parameters without an option.
+    pub extra_args: Option<Vec<String>>,
+}
+
+#[derive(Debug, Error)]
+pub enum OptionError {
+    #[error("{option} must be between {min} and {max}, not {value}")]
+    OutOfRange {
+        option: &'static str,
+        value: i64,
+        min: i64,
+        max: i64,
+    },
+
+    #[error("{option} must be one of {allowed:?}, not {value}")]
+    NotAllowed {
+        option: &'static str,
+        value: i64,
+        allowed: &'static [i64],
+    },
+
+    #[error("{option} is not an IP address: {value}")]
+    InvalidIp { option: &'static str, value: String },
+}
+
+impl OptionError {
+    /// The name of the invalid option.
+    pub fn option(&self) -> &'static str {
+        match self {
+            Self::OutOfRange { option, .. }
+            | Self::NotAllowed { option, .. }
+            | Self::InvalidIp { option, .. } => option,
+        }
+    }
+}
+
+macro_rules! check_range {
+    ($s:expr, $a:ident, $min:expr, $max:expr) => {
+        if let Some(value) = $s.$a {
+            let value = i64::from(value);
+            if !($min..=$max).contains(&value) {
+                return Err(OptionError::OutOfRange {
+                    option: stringify!($a),
+                    value,
+                    min: $min,
+                    max: $max,
+                });
+            }
+        }
+    };
+}
+
+macro_rules! check_allowed {
+    ($s:expr, $a:ident, $allowed:expr) => {
+        if let Some(value) = $s.$a {
+            let value = i64::from(value);
+            if !$allowed.contains(&value) {
+                return Err(OptionError::NotAllowed {
+                    option: stringify!($a),
+                    value,
+                    allowed: $allowed,
+                });
+            }
+        }
+    };
 }
 
 macro_rules! merge {
-    ($s:expr, $o:expr, $a:ident) => {
-        $s.$a.clone().or($o.$a)
+    ($s:expr, $o:expr, $sources:expr, $source:expr; $($a:ident),* $(,)?) => {
+        $(
+            if $o.$a.is_some() {
+                $s.$a = $o.$a;
+                $sources.insert(stringify!($a).to_owned(), $source.to_owned());
+            }
+        )*
     };
 }
 
@@ -28,29 +123,185 @@ impl OptionSet {
         Self::default()
     }
 
-    pub fn merge(&mut self, other: Self) {
-        *self = Self {
-            port: merge!(self, other, port).or(Some(2302)),
-            ranking: merge!(self, other, ranking),
-            load_mission_to_memory: merge!(self, other, load_mission_to_memory).or(Some(true)),
-            bandwidth_algorithm: merge!(self, other, bandwidth_algorithm).or(Some(2)),
-            cpu_count: merge!(self, other, cpu_count).map(|x| {
-                if x.is_negative() {
-                    num_cpus::get() as i8
-                } else {
-                    x
-                }
-            }),
-            ex_threads: merge!(self, other, ex_threads),
-            enable_ht: merge!(self, other, enable_ht),
-            hugepages: merge!(self, other, hugepages),
-            auto_init: merge!(self, other, auto_init),
-
-            basic: merge!(self, other, basic),
-            config: merge!(self, other, config),
-            profile: merge!(self, other, profile),
-            modpack: merge!(self, other, modpack),
-            server_modpack: merge!(self, other, server_modpack),
+    /// The options the server is started with when no option set changes them.
+    pub fn defaults() -> Self {
+        Self {
+            port: Some(2302),
+            load_mission_to_memory: Some(true),
+            bandwidth_algorithm: Some(2),
+            ..Self::default()
+        }
+    }
+
+    /// Check that the options are in the ranges the server accepts.
+    pub fn validate(&self) -> Result<(), OptionError> {
+        check_range!(self, port, 1, i64::from(u16::MAX - 4));
+        check_allowed!(self, bandwidth_algorithm, &[1, 2]);
+        check_range!(self, cpu_count, 1, num_cpus::get() as i64);
+        check_allowed!(self, ex_threads, &[0, 1, 3, 5, 7]);
+        check_range!(self, limit_fps, 5, 1000);
+        check_range!(self, max_mem, 1024, i64::from(u32::MAX));
+        check_range!(self, max_vram, 128, i64::from(u32::MAX));
+
+        if let Some(ip) = &self.ip {
+            if ip.parse::<IpAddr>().is_err() {
+                return Err(OptionError::InvalidIp {
+                    option: "ip",
+                    value: ip.clone(),
+                });
+            }
         }
+
+        Ok(())
+    }
+
+    /// Override the options with the ones set in `other`, recording `source` for each of them.
+    fn merge(&mut self, other: Self, source: &str, sources: &mut BTreeMap<String, String>) {
+        merge!(self, other, sources, source;
+            binary,
+            port,
+            ranking,
+            load_mission_to_memory,
+            bandwidth_algorithm,
+            cpu_count,
+            ex_threads,
+            enable_ht,
+            hugepages,
+            auto_init,
+            limit_fps,
+            malloc,
+            max_mem,
+            max_vram,
+            no_sound,
+            world,
+            bepath,
+            file_patching,
+            netlog,
+            debug_call_extension,
+            no_logs,
+            ip,
+            basic,
+            config,
+            profile,
+            profiles,
+            modpack,
+            server_modpack,
+            extra_args,
+        );
+    }
+}
+
+/// Option sets stacked from the lowest to the highest precedence.
+pub struct OptionStack {
+    layers: Vec<(String, OptionSet)>,
+}
+
+impl OptionStack {
+    /// Create a stack with the defaults at the bottom.
+    pub fn new() -> Self {
+        Self {
+            layers: vec![(String::from("default"), OptionSet::defaults())],
+        }
+    }
+
+    /// Put `options` on top of the stack. `source` names where they came from, like
+    /// `options.global`.
+    pub fn push<S>(&mut self, source: S, options: OptionSet)
+    where
+        S: Into<String>,
+    {
+        self.layers.push((source.into(), options));
+    }
+
+    /// Merge the layers, every option taking the value of the highest layer that sets it.
+    ///
+    /// Returns the merged options and the source of each option that is set.
+    pub fn resolve(self) -> (OptionSet, BTreeMap<String, String>) {
+        let mut options = OptionSet::new();
+        let mut sources = BTreeMap::new();
+
+        for (source, layer) in self.layers {
+            options.merge(layer, &source, &mut sources);
+        }
+
+        // a negative cpu count uses all cores
+        if let Some(cpu_count) = options.cpu_count.filter(|x| x.is_negative()) {
+            debug!("Using all cores instead of cpu count {}", cpu_count);
+            options.cpu_count = Some(num_cpus::get() as i8);
+        }
+
+        (options, sources)
+    }
+}
+
+impl Default for OptionStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_option_stack() {
+        let mut stack = OptionStack::new();
+        stack.push(
+            "options.global",
+            OptionSet {
+                port: Some(2402),
+                modpack: Some(String::from("global")),
+                ..OptionSet::new()
+            },
+        );
+        stack.push(
+            "options.main",
+            OptionSet {
+                modpack: Some(String::from("main")),
+                ..OptionSet::new()
+            },
+        );
+        stack.push("command line", OptionSet::new());
+
+        let (options, sources) = stack.resolve();
+        assert_eq!(options.port, Some(2402));
+        assert_eq!(options.modpack.as_deref(), Some("main"));
+        assert_eq!(options.bandwidth_algorithm, Some(2));
+
+        assert_eq!(sources["port"], "options.global");
+        assert_eq!(sources["modpack"], "options.main");
+        assert_eq!(sources["bandwidth_algorithm"], "default");
+        assert!(!sources.contains_key("config"));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(OptionSet::defaults().validate().is_ok());
+
+        let options = OptionSet {
+            limit_fps: Some(2000),
+            ..OptionSet::defaults()
+        };
+        assert_eq!(options.validate().unwrap_err().option(), "limit_fps");
+
+        let options = OptionSet {
+            ex_threads: Some(2),
+            ..OptionSet::defaults()
+        };
+        assert_eq!(options.validate().unwrap_err().option(), "ex_threads");
+
+        let options = OptionSet {
+            ip: Some(String::from("0.0.0.0")),
+            max_mem: Some(8192),
+            ..OptionSet::defaults()
+        };
+        assert!(options.validate().is_ok());
+
+        let options = OptionSet {
+            ip: Some(String::from("localhost")),
+            ..OptionSet::defaults()
+        };
+        assert_eq!(options.validate().unwrap_err().option(), "ip");
     }
 }
@@ -0,0 +1,126 @@
+use anyhow::Result;
+use chrono::prelude::*;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+use tinytemplate::TinyTemplate;
+
+/// The `amraam@.service` template unit shared by all instances and option sets.
+#[derive(Serialize)]
+pub struct SystemdUnit {
+    pub user: String,
+    pub working_directory: String,
+    /// Path to the amraam binary.
+    pub executable: String,
+    /// Path to the amraam config.
+    pub config: String,
+    pub restart_sec: u64,
+    pub start_limit_interval: u64,
+    pub start_limit_burst: usize,
+    pub timeout_stop_sec: u64,
+    pub limit_nofile: u64,
+}
+
+#[derive(Serialize)]
+struct Context<'a> {
+    timestamp: String,
+    unit: &'a SystemdUnit,
+}
+
+impl SystemdUnit {
+    pub const NAME: &'static str = "amraam@.service";
+
+    pub fn generate<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        lazy_static! {
+            static ref TEMPLATE: &'static str = include_str!("../../assets/amraam@.service.in");
+        }
+
+        let mut tt = TinyTemplate::new();
+        tt.set_default_formatter(&super::format);
+        tt.add_template("template", &TEMPLATE)?;
+
+        let context = Context {
+            timestamp: Local::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            unit: self,
+        };
+
+        let rendered = tt.render("template", &context)?;
+
+        let mut file = File::create(path)?;
+        write!(file, "{}", rendered)?;
+
+        Ok(())
+    }
+
+    /// Write the drop-in of the unit instance `name` that pins the server to the first
+    /// `cpu_count` CPUs.
+    pub fn generate_instance<P>(unit_dir: P, name: &str, cpu_count: Option<i8>) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let drop_in_dir = unit_dir.as_ref().join(format!("amraam@{}.service.d", name));
+        let drop_in = drop_in_dir.join("amraam.conf");
+
+        match cpu_count.filter(|&n| n > 0) {
+            Some(n) => {
+                fs::create_dir_all(&drop_in_dir)?;
+
+                let mut file = File::create(drop_in)?;
+                writeln!(file, "# Generated by AMRAAM")?;
+                writeln!(file, "[Service]")?;
+                writeln!(file, "CPUAffinity=0-{}", n - 1)?;
+            }
+            None => {
+                if drop_in.exists() {
+                    fs::remove_file(drop_in)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let unit = SystemdUnit {
+            user: "arma".into(),
+            working_directory: "/srv/arma3".into(),
+            executable: "/usr/local/bin/amraam".into(),
+            config: "/srv/arma3/amraam.toml".into(),
+            restart_sec: 5,
+            start_limit_interval: 600,
+            start_limit_burst: 5,
+            timeout_stop_sec: 120,
+            limit_nofile: 100000,
+        };
+        unit.generate(dir.path().join(SystemdUnit::NAME)).unwrap();
+
+        let rendered = fs::read_to_string(dir.path().join(SystemdUnit::NAME)).unwrap();
+        assert!(rendered.contains("\nUser=arma\n"));
+        assert!(rendered.contains("\nWorkingDirectory=/srv/arma3\n"));
+        assert!(rendered.contains(
+            "\nExecStart=/usr/local/bin/amraam --config /srv/arma3/amraam.toml --instance %i run\n"
+        ));
+        assert!(rendered.contains("\nStartLimitBurst=5\n"));
+        assert!(rendered.contains("\nLimitNOFILE=100000\n"));
+
+        let drop_in = dir.path().join("amraam@main.service.d/amraam.conf");
+        SystemdUnit::generate_instance(dir.path(), "main", Some(4)).unwrap();
+        assert!(fs::read_to_string(&drop_in)
+            .unwrap()
+            .contains("\nCPUAffinity=0-3\n"));
+
+        SystemdUnit::generate_instance(dir.path(), "main", None).unwrap();
+        assert!(!drop_in.exists());
+    }
+}
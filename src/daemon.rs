@@ -0,0 +1,229 @@
+//! PID files of running servers.
+//!
+//! Every running option set holds an exclusive lock on its PID file in `<server>/.run`, so the
+//! same option set can not be started twice and a PID file left behind by a killed process is
+//! recognized as stale. Next to the PID file a state file records when the server was started and
+//! its command line.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use nix::fcntl::{flock, FlockArg};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
+use thiserror::Error;
+
+/// How often an empty PID file is read again before the server is considered not started yet.
+const PID_RETRIES: usize = 10;
+
+/// Name used for the PID file when no option set is given.
+pub static DEFAULT_NAME: &str = "global";
+
+#[derive(Debug, Error)]
+pub enum DaemonError {
+    #[error("Server {0} is already running")]
+    AlreadyRunning(String),
+}
+
+/// What is known about a running server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub pid: i32,
+    /// Unix timestamp of the start.
+    pub started: i64,
+    /// The command line of the server.
+    pub command: Vec<String>,
+}
+
+/// Get the directory the PID files of the server at `server_path` are kept in.
+pub fn run_dir<P>(server_path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    server_path.as_ref().join(".run")
+}
+
+fn pid_path(server_path: &Path, name: &str) -> PathBuf {
+    run_dir(server_path).join(format!("{}.pid", name))
+}
+
+fn state_path(server_path: &Path, name: &str) -> PathBuf {
+    run_dir(server_path).join(format!("{}.json", name))
+}
+
+/// The locked PID file of the current process. The files are removed when it is dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    pid_path: PathBuf,
+    state_path: PathBuf,
+    // keeps the lock
+    _file: File,
+}
+
+impl PidFile {
+    /// Lock the PID file of `name` and write the PID of the current process to it.
+    pub fn acquire<P>(server_path: P, name: &str) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let server_path = server_path.as_ref();
+        let pid_path = pid_path(server_path, name);
+
+        fs::create_dir_all(run_dir(server_path)).context("Could not create run directory")?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&pid_path)
+            .with_context(|| format!("Could not open PID file {}", pid_path.display()))?;
+
+        if flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err() {
+            return Err(DaemonError::AlreadyRunning(name.to_owned()).into());
+        }
+
+        file.set_len(0).context("Could not truncate PID file")?;
+        writeln!(file, "{}", process::id()).context("Could not write PID file")?;
+
+        Ok(Self {
+            pid_path,
+            state_path: state_path(server_path, name),
+            _file: file,
+        })
+    }
+
+    /// Record the command line of the server.
+    pub fn set_command(&self, command: Vec<String>) -> Result<()> {
+        let state = State {
+            pid: process::id() as i32,
+            started: Utc::now().timestamp(),
+            command,
+        };
+
+        fs::write(
+            &self.state_path,
+            serde_json::to_string(&state).context("Could not serialize state")?,
+        )
+        .context("Could not write state file")
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.state_path);
+        let _ = fs::remove_file(&self.pid_path);
+    }
+}
+
+/// Get the state of the server `name` or `None` if it is not running or still starting.
+pub fn status<P>(server_path: P, name: &str) -> Result<Option<State>>
+where
+    P: AsRef<Path>,
+{
+    let server_path = server_path.as_ref();
+    let pid_path = pid_path(server_path, name);
+
+    let file = match File::open(&pid_path) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+
+    // the lock can only be taken if the process holding it is gone
+    if flock(file.as_raw_fd(), FlockArg::LockSharedNonblock).is_ok() {
+        debug!("PID file {} is stale", pid_path.display());
+        return Ok(None);
+    }
+
+    // the PID is written right after the lock is taken, so an empty file belongs to a server
+    // that is just starting
+    let mut pid = None;
+    for _ in 0..PID_RETRIES {
+        pid = fs::read_to_string(&pid_path)
+            .context("Could not read PID file")?
+            .trim()
+            .parse::<i32>()
+            .ok();
+
+        if pid.is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let pid = match pid {
+        Some(pid) => pid,
+        None => {
+            debug!("PID file {} has no PID yet", pid_path.display());
+            return Ok(None);
+        }
+    };
+
+    let state = fs::read_to_string(state_path(server_path, name))
+        .ok()
+        .and_then(|content| serde_json::from_str::<State>(&content).ok())
+        .filter(|state| state.pid == pid);
+
+    Ok(Some(
+        state.unwrap_or(State {
+            pid,
+            started: fs::metadata(&pid_path)
+                .and_then(|m| m.modified())
+                .map(|modified| chrono::DateTime::<Utc>::from(modified).timestamp())
+                .unwrap_or_default(),
+            command: Vec::new(),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(status(dir.path(), "main").unwrap(), None);
+
+        let pid_file = PidFile::acquire(dir.path(), "main").unwrap();
+        pid_file
+            .set_command(vec!["./arma3server_x64".into(), "-port=2302".into()])
+            .unwrap();
+
+        // flock locks are per open file, so a second acquire fails even in the same process
+        assert!(PidFile::acquire(dir.path(), "main").is_err());
+        assert!(PidFile::acquire(dir.path(), "training").is_ok());
+
+        let state = status(dir.path(), "main").unwrap().unwrap();
+        assert_eq!(state.pid, process::id() as i32);
+        assert_eq!(state.command[1], "-port=2302");
+
+        drop(pid_file);
+        assert_eq!(status(dir.path(), "main").unwrap(), None);
+    }
+
+    #[test]
+    fn test_stale_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(run_dir(dir.path())).unwrap();
+        fs::write(pid_path(dir.path(), "main"), "12345\n").unwrap();
+
+        assert_eq!(status(dir.path(), "main").unwrap(), None);
+        assert!(PidFile::acquire(dir.path(), "main").is_ok());
+    }
+
+    #[test]
+    fn test_empty_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let _pid_file = PidFile::acquire(dir.path(), "main").unwrap();
+
+        // the lock is held but the PID is not written yet
+        fs::write(pid_path(dir.path(), "main"), "").unwrap();
+        assert_eq!(status(dir.path(), "main").unwrap(), None);
+    }
+}
@@ -5,10 +5,21 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
-//pub mod arma_config;
+pub mod addons;
+pub mod archive;
+pub mod arma_config;
 pub mod config;
+pub mod daemon;
+pub mod logs;
+pub mod mission;
+pub mod pbo;
+pub mod ports;
+pub mod rap;
+pub mod rcon;
+pub mod rpt;
 pub mod settings;
 pub mod steamcmd;
+pub mod supervisor;
 pub mod tools;
 pub mod util;
 
@@ -0,0 +1,417 @@
+//! Rotation of server logs.
+//!
+//! The server writes a new `.rpt` file to its profiles directory on every start and its output is
+//! captured in `server_<time>.log` next to it. Logs that are not written anymore are compressed
+//! into `<profiles>/archive`, output logs exceeding the size or age limit are continued in a new
+//! file while the server runs. Archives older than the age limit or beyond the configured number
+//! are removed.
+//!
+//! The server keeps its `.rpt` file open and writes to it at its own offset, so the RPT file of
+//! a running server cannot be truncated or moved. It is only archived once the server restarts
+//! and is not limited by the size or age limit until then; a scheduled restart bounds it.
+
+use crate::{config::OptionSet, Settings};
+use anyhow::{Context, Result};
+use chrono::Local;
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::Child,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+/// The directory in the profiles directory compressed logs are moved to.
+pub const ARCHIVE_DIR: &str = "archive";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogKind {
+    /// The report files the server writes.
+    Rpt,
+    /// The captured stdout and stderr of the server.
+    Output,
+}
+
+impl LogKind {
+    fn extension(self) -> &'static str {
+        match self {
+            LogKind::Rpt => "rpt",
+            LogKind::Output => "log",
+        }
+    }
+}
+
+/// When logs are rotated and how many archives are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogPolicy {
+    /// Whether the output of the server is captured.
+    pub capture: bool,
+    /// The size in bytes after which an output log is continued in a new file.
+    pub max_size: u64,
+    /// The age after which an output log is continued in a new file and archives are removed.
+    pub max_age: Duration,
+    /// The number of archives to keep.
+    pub keep: usize,
+}
+
+impl Default for LogPolicy {
+    fn default() -> Self {
+        Self {
+            capture: true,
+            max_size: 50 * 1024 * 1024,
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+            keep: 20,
+        }
+    }
+}
+
+impl LogPolicy {
+    /// Read the policy from the `logs` table. `max_size` is in MB and `max_age` in days.
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        let default = Self::default();
+        let int = |key: &str| -> Result<Option<u64>> {
+            Ok(settings
+                .get_int(&format!("logs.{}", key))
+                .with_context(|| format!("Could not get logs.{} from config", key))?
+                .map(|value| value.max(0) as u64))
+        };
+
+        Ok(Self {
+            capture: settings
+                .get_bool("logs.capture")
+                .context("Could not get logs.capture from config")?
+                .unwrap_or(default.capture),
+            max_size: int("max_size")?
+                .map(|mb| mb * 1024 * 1024)
+                .unwrap_or(default.max_size),
+            max_age: int("max_age")?
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+                .unwrap_or(default.max_age),
+            keep: int("keep")?
+                .map(|keep| keep as usize)
+                .unwrap_or(default.keep),
+        })
+    }
+}
+
+/// Get the directory the server with `options` writes its logs to.
+///
+/// Without `profiles` the server writes its logs to the home directory of the server user, so
+/// they are not managed.
+pub fn log_dir<P>(server_path: P, options: &OptionSet) -> Option<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    options
+        .profiles
+        .as_ref()
+        .map(|profiles| server_path.as_ref().join(profiles))
+}
+
+/// Get the files in `dir` with `extension` from the oldest to the newest.
+fn files_by_age(dir: &Path, extension: &str) -> Result<Vec<(SystemTime, PathBuf)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir).context("Could not read log directory")? {
+        let path = entry.context("Could not read log directory entry")?.path();
+
+        if path.is_file() && path.extension().is_some_and(|ext| ext == extension) {
+            let modified = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .with_context(|| format!("Could not get age of {}", path.display()))?;
+            files.push((modified, path));
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Get the logs of `kind` in `dir` from the oldest to the newest.
+pub fn logs(dir: &Path, kind: LogKind) -> Result<Vec<PathBuf>> {
+    Ok(files_by_age(dir, kind.extension())?
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Get the log of `kind` that was written last.
+pub fn current(dir: &Path, kind: LogKind) -> Result<Option<PathBuf>> {
+    Ok(logs(dir, kind)?.pop())
+}
+
+/// Create a new file `<stem>.<extension>` in `dir`, adding a counter to the name if a file with
+/// that name already exists.
+fn create_new(dir: &Path, stem: &str, extension: &str) -> Result<(PathBuf, File)> {
+    for n in 0.. {
+        let path = match n {
+            0 => dir.join(format!("{}.{}", stem, extension)),
+            n => dir.join(format!("{}_{}.{}", stem, n, extension)),
+        };
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Could not create {}", path.display()))
+            }
+        }
+    }
+
+    unreachable!()
+}
+
+/// Compress the log at `path` into the archive directory and remove it.
+fn archive(dir: &Path, path: &Path) -> Result<()> {
+    let archive_dir = dir.join(ARCHIVE_DIR);
+    fs::create_dir_all(&archive_dir).context("Could not create archive directory")?;
+
+    let file_name = path.file_name().context("Log has no file name")?;
+
+    let mut log = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    // an existing archive of a log with the same name is kept
+    let (_, archive) = create_new(&archive_dir, &file_name.to_string_lossy(), "gz")?;
+    let mut encoder = GzEncoder::new(archive, Compression::default());
+
+    io::copy(&mut log, &mut encoder).context("Could not compress log")?;
+    encoder.finish().context("Could not compress log")?;
+
+    fs::remove_file(path).with_context(|| format!("Could not remove {}", path.display()))
+}
+
+/// Archive the logs in `dir` that are not written anymore and remove old archives.
+///
+/// If the server is `running`, the newest log of each kind is still written to and kept.
+pub fn rotate(dir: &Path, policy: &LogPolicy, running: bool) -> Result<()> {
+    for &kind in &[LogKind::Rpt, LogKind::Output] {
+        let mut logs = logs(dir, kind)?;
+        if running {
+            logs.pop();
+        }
+
+        for log in logs {
+            archive(dir, &log)?;
+        }
+    }
+
+    let now = SystemTime::now();
+    let archives = files_by_age(&dir.join(ARCHIVE_DIR), "gz")?;
+    let count = archives.len();
+
+    for (i, (modified, path)) in archives.into_iter().enumerate() {
+        let too_many = count - i > policy.keep;
+        let too_old = now.duration_since(modified).unwrap_or_default() > policy.max_age;
+
+        if too_many || too_old {
+            fs::remove_file(&path)
+                .with_context(|| format!("Could not remove archive {}", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The captured output of the server.
+pub struct OutputLog {
+    dir: PathBuf,
+    policy: LogPolicy,
+    /// Also print the output to stdout.
+    echo: bool,
+    file: Option<File>,
+    written: u64,
+    opened: SystemTime,
+}
+
+impl OutputLog {
+    pub fn new<P>(dir: P, policy: LogPolicy, echo: bool) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            dir: dir.as_ref().to_owned(),
+            policy,
+            echo,
+            file: None,
+            written: 0,
+            opened: SystemTime::now(),
+        }
+    }
+
+    /// Archive the logs of the last run and start a new output log.
+    pub fn start(&mut self) -> Result<()> {
+        self.file = None;
+        rotate(&self.dir, &self.policy, false)?;
+        self.open()
+    }
+
+    fn open(&mut self) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Could not create log directory")?;
+
+        let stem = format!("server_{}", Local::now().format("%Y-%m-%d_%H-%M-%S-%3f"));
+        let (_, file) = create_new(&self.dir, &stem, LogKind::Output.extension())
+            .context("Could not open output log")?;
+
+        self.file = Some(file);
+        self.written = 0;
+        self.opened = SystemTime::now();
+
+        Ok(())
+    }
+
+    /// Write a line of output, continuing in a new file if the current one is too large or old.
+    pub fn write_line(&mut self, line: &[u8]) -> Result<()> {
+        let age = self.opened.elapsed().unwrap_or_default();
+        if self.written >= self.policy.max_size || age >= self.policy.max_age {
+            self.open()?;
+            rotate(&self.dir, &self.policy, true)?;
+        }
+
+        if let Some(file) = &mut self.file {
+            file.write_all(line).context("Could not write output log")?;
+            self.written += line.len() as u64;
+        }
+
+        if self.echo {
+            io::stdout()
+                .write_all(line)
+                .context("Could not write output")?;
+        }
+
+        Ok(())
+    }
+
+    /// Capture the output of `child`, which has to be started with piped stdout and stderr.
+    ///
+    /// The returned threads finish when the server closes its output.
+    pub fn capture(log: &Arc<Mutex<Self>>, child: &mut Child) -> Vec<JoinHandle<()>> {
+        let stdout = child
+            .stdout
+            .take()
+            .map(|out| Box::new(out) as Box<dyn Read + Send>);
+        let stderr = child
+            .stderr
+            .take()
+            .map(|err| Box::new(err) as Box<dyn Read + Send>);
+
+        stdout
+            .into_iter()
+            .chain(stderr)
+            .map(|output| {
+                let log = Arc::clone(log);
+
+                thread::spawn(move || {
+                    let mut output = BufReader::new(output);
+                    let mut line = Vec::new();
+
+                    loop {
+                        line.clear();
+                        match output.read_until(b'\n', &mut line) {
+                            Ok(0) => break,
+                            Ok(_) => {
+                                let mut log = log.lock().unwrap_or_else(|err| err.into_inner());
+                                if let Err(err) = log.write_line(&line) {
+                                    debug!("Could not capture output: {:?}", err);
+                                }
+                            }
+                            Err(err) => {
+                                debug!("Could not read output: {:?}", err);
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = LogPolicy {
+            keep: 2,
+            ..LogPolicy::default()
+        };
+
+        for name in &["a.rpt", "b.rpt", "c.rpt"] {
+            fs::write(dir.path().join(name), name).unwrap();
+            // modification times have to differ to order the logs
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        rotate(dir.path(), &policy, true).unwrap();
+        assert_eq!(
+            logs(dir.path(), LogKind::Rpt).unwrap(),
+            vec![dir.path().join("c.rpt")]
+        );
+
+        let mut content = String::new();
+        GzDecoder::new(File::open(dir.path().join(ARCHIVE_DIR).join("b.rpt.gz")).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "b.rpt");
+
+        // the oldest archive is removed once there are more than two
+        rotate(dir.path(), &policy, false).unwrap();
+        assert!(logs(dir.path(), LogKind::Rpt).unwrap().is_empty());
+        assert_eq!(
+            files_by_age(&dir.path().join(ARCHIVE_DIR), "gz")
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(!dir.path().join(ARCHIVE_DIR).join("a.rpt.gz").exists());
+    }
+
+    #[test]
+    fn test_output_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = LogPolicy {
+            max_size: 10,
+            ..LogPolicy::default()
+        };
+
+        let mut log = OutputLog::new(dir.path(), policy, false);
+        log.start().unwrap();
+        log.write_line(b"first line\n").unwrap();
+        log.write_line(b"second line\n").unwrap();
+        log.write_line(b"third line\n").unwrap();
+
+        let current = current(dir.path(), LogKind::Output).unwrap().unwrap();
+        assert_eq!(fs::read_to_string(current).unwrap(), "third line\n");
+        assert_eq!(
+            files_by_age(&dir.path().join(ARCHIVE_DIR), "gz")
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_archive_keeps_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("server.rpt");
+
+        fs::write(&log, "first").unwrap();
+        archive(dir.path(), &log).unwrap();
+        fs::write(&log, "second").unwrap();
+        archive(dir.path(), &log).unwrap();
+
+        let archive_dir = dir.path().join(ARCHIVE_DIR);
+        assert!(archive_dir.join("server.rpt.gz").exists());
+        assert!(archive_dir.join("server.rpt_1.gz").exists());
+    }
+}
@@ -19,6 +19,8 @@ fn main() -> Result<()> {
             Ok(_) => {}
             Err(err) => {
                 stdout.write_line(&format!("{}: {:?}", style("Error").red(), err))?;
+                // a failing exit code lets systemd restart the server
+                std::process::exit(1);
             }
         }
     } else {
@@ -0,0 +1,373 @@
+use crate::{
+    arma_config::{Cfg, Value},
+    pbo::Pbo,
+    rap,
+};
+use anyhow::{Context, Result};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use walkdir::WalkDir;
+
+#[derive(Debug, Error)]
+pub enum MissionError {
+    #[error("Mission name `{0}` does not follow the `<name>.<map>` naming convention")]
+    InvalidName(String),
+}
+
+/// The name of a mission file or directory in the form `<name>.<map>` or `<name>.<map>.pbo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissionName {
+    pub name: String,
+    /// The terrain the mission is played on, e.g. `Altis`.
+    pub map: String,
+    /// Whether the mission is packed into a PBO.
+    pub packed: bool,
+}
+
+impl MissionName {
+    pub fn parse(file_name: &str) -> Result<Self> {
+        let invalid = || MissionError::InvalidName(file_name.to_owned());
+
+        // hidden files are missions staged or replaced by `missions install`
+        if file_name.starts_with('.') {
+            return Err(invalid().into());
+        }
+
+        let (stem, packed) = match file_name.len().checked_sub(4) {
+            Some(i)
+                if file_name.is_char_boundary(i) && file_name[i..].eq_ignore_ascii_case(".pbo") =>
+            {
+                (&file_name[..i], true)
+            }
+            _ => (file_name, false),
+        };
+
+        let dot = stem.rfind('.').ok_or_else(invalid)?;
+        let (name, map) = (&stem[..dot], &stem[dot + 1..]);
+
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c));
+        let valid_map = !map.is_empty()
+            && map
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+        if !valid_name || !valid_map {
+            return Err(invalid().into());
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            map: map.to_owned(),
+            packed,
+        })
+    }
+
+    pub fn from_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| MissionError::InvalidName(path.display().to_string()))?;
+
+        Self::parse(file_name)
+    }
+
+    /// The name of the mission file or directory.
+    pub fn file_name(&self) -> String {
+        if self.packed {
+            format!("{}.pbo", self)
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+/// Formats the name like the server config expects it in `template` or `missionWhitelist`.
+impl fmt::Display for MissionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.name, self.map)
+    }
+}
+
+/// A mission installed in `mpmissions`, either packed or as a directory.
+#[derive(Debug, Clone)]
+pub struct Mission {
+    pub path: PathBuf,
+    pub name: MissionName,
+}
+
+impl Mission {
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        Ok(Self {
+            name: MissionName::from_path(path)?,
+            path: path.to_owned(),
+        })
+    }
+
+    /// The size of the PBO or the sum of all files in the mission directory.
+    pub fn size(&self) -> Result<u64> {
+        if self.name.packed {
+            return Ok(fs::metadata(&self.path)
+                .context("Could not get metadata")?
+                .len());
+        }
+
+        WalkDir::new(&self.path)
+            .into_iter()
+            .map(|entry| {
+                let metadata = entry
+                    .context("Could not get path")?
+                    .metadata()
+                    .context("Could not get metadata")?;
+                Ok(if metadata.is_file() {
+                    metadata.len()
+                } else {
+                    0
+                })
+            })
+            .sum()
+    }
+
+    /// Read a file of the mission. `name` is relative to the mission root.
+    pub fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+        if self.name.packed {
+            Pbo::open(&self.path)?.read_file(name)
+        } else {
+            fs::read(self.path.join(name)).with_context(|| format!("Could not read {}", name))
+        }
+    }
+
+    /// Read and parse the `mission.sqm`.
+    pub fn mission_sqm(&self) -> Result<Cfg> {
+        let data = self.read_file("mission.sqm")?;
+
+        if rap::is_rapified(&data) {
+            return rap::from_bytes(&data).context("Could not parse binarized mission.sqm");
+        }
+
+        Cfg::from_string(&String::from_utf8_lossy(&data)).context("Could not parse mission.sqm")
+    }
+
+    /// Check the mission for problems that keep the server from loading it.
+    ///
+    /// Returns a description of every problem found.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if !self.name.packed {
+            if !self.path.join("mission.sqm").is_file() {
+                problems.push("Mission directory does not contain a mission.sqm".to_owned());
+            }
+            return problems;
+        }
+
+        let mut pbo = match Pbo::open(&self.path) {
+            Ok(pbo) => pbo,
+            Err(err) => {
+                problems.push(format!("Corrupt PBO header: {:#}", err));
+                return problems;
+            }
+        };
+
+        if pbo.entry("mission.sqm").is_none() {
+            problems.push("PBO does not contain a mission.sqm".to_owned());
+        }
+
+        match pbo.verify() {
+            Ok(Some(true)) | Ok(None) => {}
+            Ok(Some(false)) => problems.push("PBO checksum does not match".to_owned()),
+            Err(err) => problems.push(format!("Could not verify PBO checksum: {:#}", err)),
+        }
+
+        problems
+    }
+
+    /// The name shown in the mission selection, read from the `mission.sqm`.
+    pub fn briefing_name(&self) -> Result<Option<String>> {
+        Ok(self
+            .mission_sqm()?
+            .root()
+            .get_path("Mission/Intel/briefingName")
+            .and_then(Value::as_str)
+            .map(str::to_owned))
+    }
+}
+
+/// List all missions in the `mpmissions` directory, skipping hidden files.
+pub fn list_missions<P>(path: P) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let mut missions = fs::read_dir(path)
+        .context("Could not read directory contents")?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect::<Vec<PathBuf>>();
+
+    missions.sort();
+
+    Ok(missions)
+}
+
+/// Find a mission by its file name. The `.pbo` extension and the case of the name are optional.
+pub fn find_mission<P>(path: P, name: &str) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+
+    for candidate in &[name.to_owned(), format!("{}.pbo", name)] {
+        let mission_path = path.join(candidate);
+        if mission_path.exists() {
+            return Ok(mission_path);
+        }
+    }
+
+    let name = name.to_lowercase();
+    let packed_name = format!("{}.pbo", name);
+
+    list_missions(path)
+        .context("Could not list missions")?
+        .into_iter()
+        .find(|mission_path| {
+            mission_path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_lowercase())
+                .map(|n| n == name || n == packed_name)
+                .unwrap_or(false)
+        })
+        .with_context(|| format!("Mission {} not found", name))
+}
+
+/// The missions a server config refers to.
+#[derive(Debug, Default)]
+pub struct MissionRotation {
+    /// The `template`s of the `Missions` class.
+    pub rotation: Vec<String>,
+    /// The entries of `missionWhitelist`.
+    pub whitelist: Vec<String>,
+}
+
+impl MissionRotation {
+    pub fn from_server_config(config: &Cfg) -> Self {
+        let rotation = config
+            .root()
+            .get("Missions")
+            .and_then(Value::as_class)
+            .map(|missions| {
+                missions
+                    .values()
+                    .filter_map(|m| m.get("template").and_then(Value::as_str))
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let whitelist = config
+            .root()
+            .get("missionWhitelist")
+            .and_then(Value::as_list)
+            .map(|list| {
+                list.iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            rotation,
+            whitelist,
+        }
+    }
+
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read server config {}", path.display()))?;
+
+        Ok(Self::from_server_config(&Cfg::from_string(&content)?))
+    }
+
+    pub fn in_rotation(&self, mission: &MissionName) -> bool {
+        Self::contains(&self.rotation, mission)
+    }
+
+    pub fn in_whitelist(&self, mission: &MissionName) -> bool {
+        Self::contains(&self.whitelist, mission)
+    }
+
+    fn contains(list: &[String], mission: &MissionName) -> bool {
+        let name = mission.to_string();
+
+        list.iter().any(|m| {
+            let m = m.trim_end_matches(".pbo");
+            m.eq_ignore_ascii_case(&name)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            MissionName::parse("co10_Escape.Altis.pbo").unwrap(),
+            MissionName {
+                name: "co10_Escape".into(),
+                map: "Altis".into(),
+                packed: true,
+            }
+        );
+        assert_eq!(
+            MissionName::parse("tvt.v2.Tanoa").unwrap().file_name(),
+            "tvt.v2.Tanoa"
+        );
+        assert!(MissionName::parse("mission").is_err());
+        assert!(MissionName::parse("mission.pbo").is_err());
+        assert!(MissionName::parse(".Altis").is_err());
+        assert!(MissionName::parse("my mission.Altis").is_err());
+        assert!(MissionName::parse(".co10_escape.Altis.pbo.new").is_err());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let config = Cfg::from_string(
+            r#"
+class Missions {
+    class Mission1 { template = "co10_Escape.Altis"; difficulty = "Regular"; };
+};
+missionWhitelist[] = { "tvt.Tanoa" };
+"#,
+        )
+        .unwrap();
+        let rotation = MissionRotation::from_server_config(&config);
+
+        let escape = MissionName::parse("co10_escape.altis.pbo").unwrap();
+        let tvt = MissionName::parse("tvt.Tanoa").unwrap();
+
+        assert!(rotation.in_rotation(&escape));
+        assert!(!rotation.in_whitelist(&escape));
+        assert!(rotation.in_whitelist(&tvt));
+    }
+}
@@ -0,0 +1,680 @@
+//! Reading and writing of Arma PBO archives.
+//!
+//! A PBO starts with a list of header entries, each describing one file. The first entry may be
+//! a product entry which is followed by a list of properties like `prefix`. The header is
+//! terminated by an empty entry and followed by the file data in the same order as the header
+//! entries. Arma 3 PBOs end with a zero byte and the SHA1 hash of everything before it.
+
+use anyhow::{ensure, Context, Result};
+use sha1::{Digest, Sha1};
+use std::{
+    fs::{self, File},
+    io::{self, prelude::*, BufReader, BufWriter, SeekFrom},
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use thiserror::Error;
+use walkdir::WalkDir;
+
+const PRODUCT_MAGIC: u32 = 0x5665_7273; // "Vers"
+const COMPRESSED_MAGIC: u32 = 0x4370_7273; // "Cprs"
+const ENCRYPTED_MAGIC: u32 = 0x456e_6372; // "Encr"
+
+#[derive(Debug, Error)]
+pub enum PboError {
+    #[error("Could not read PBO header")]
+    Header,
+
+    #[error("Could not read PBO entry {0}")]
+    Entry(String),
+
+    #[error("PBO does not contain {0}")]
+    MissingEntry(String),
+
+    #[error("PBO entry {0} is encrypted")]
+    Encrypted(String),
+
+    #[error("Compressed PBO entry {0} is corrupt")]
+    Decompress(String),
+
+    #[error("PBO entry escapes the target directory: {0}")]
+    UnsafePath(String),
+
+    #[error("Could not write PBO")]
+    Write,
+}
+
+/// How the data of an entry is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackingMethod {
+    Uncompressed,
+    Compressed,
+    Encrypted,
+    Product,
+    Unknown(u32),
+}
+
+impl From<u32> for PackingMethod {
+    fn from(n: u32) -> Self {
+        match n {
+            0 => Self::Uncompressed,
+            COMPRESSED_MAGIC => Self::Compressed,
+            ENCRYPTED_MAGIC => Self::Encrypted,
+            PRODUCT_MAGIC => Self::Product,
+            n => Self::Unknown(n),
+        }
+    }
+}
+
+impl From<PackingMethod> for u32 {
+    fn from(method: PackingMethod) -> Self {
+        match method {
+            PackingMethod::Uncompressed => 0,
+            PackingMethod::Compressed => COMPRESSED_MAGIC,
+            PackingMethod::Encrypted => ENCRYPTED_MAGIC,
+            PackingMethod::Product => PRODUCT_MAGIC,
+            PackingMethod::Unknown(n) => n,
+        }
+    }
+}
+
+/// A file stored in a PBO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The path of the file inside the PBO, separated by backslashes.
+    pub name: String,
+    pub method: PackingMethod,
+    /// The size of the file after decompression.
+    pub original_size: u32,
+    pub reserved: u32,
+    /// The modification time as seconds since the unix epoch.
+    pub timestamp: u32,
+    /// The size of the data stored in the PBO.
+    pub data_size: u32,
+    offset: u64,
+}
+
+impl Entry {
+    /// The size of the file after extracting it.
+    pub fn size(&self) -> u32 {
+        if self.method == PackingMethod::Compressed || self.data_size == 0 {
+            self.original_size
+        } else {
+            self.data_size
+        }
+    }
+
+    /// Get the path of the entry relative to the extraction directory.
+    ///
+    /// Returns `None` if the path would escape the extraction directory.
+    pub fn path(&self) -> Option<PathBuf> {
+        let path = self
+            .name
+            .split('\\')
+            .filter(|part| !part.is_empty())
+            .collect::<PathBuf>();
+
+        if path.components().all(|c| matches!(c, Component::Normal(_))) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+/// A PBO archive opened for reading.
+pub struct Pbo<R> {
+    reader: R,
+    properties: Vec<(String, String)>,
+    entries: Vec<Entry>,
+    data_end: u64,
+}
+
+impl Pbo<BufReader<File>> {
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(path).context("Could not open PBO")?;
+
+        Self::read(BufReader::new(file))
+    }
+}
+
+impl<R> Pbo<R>
+where
+    R: Read + Seek,
+{
+    /// Read the header of a PBO.
+    pub fn read(mut reader: R) -> Result<Self> {
+        let mut properties = Vec::new();
+        let mut entries = Vec::new();
+
+        loop {
+            let mut entry = read_entry(&mut reader).context(PboError::Header)?;
+
+            if entry.name.is_empty() {
+                if entry.method == PackingMethod::Product {
+                    loop {
+                        let key = read_string(&mut reader).context(PboError::Header)?;
+                        if key.is_empty() {
+                            break;
+                        }
+                        let value = read_string(&mut reader).context(PboError::Header)?;
+                        properties.push((key, value));
+                    }
+                    continue;
+                }
+
+                break;
+            }
+
+            entry.offset = entries
+                .last()
+                .map(|e: &Entry| e.offset + u64::from(e.data_size))
+                .unwrap_or(0);
+            entries.push(entry);
+        }
+
+        let data_start = reader.stream_position().context(PboError::Header)?;
+
+        for entry in entries.iter_mut() {
+            entry.offset += data_start;
+        }
+
+        let data_end = entries
+            .last()
+            .map(|e| e.offset + u64::from(e.data_size))
+            .unwrap_or(data_start);
+
+        let len = reader.seek(SeekFrom::End(0)).context(PboError::Header)?;
+
+        ensure!(
+            data_end <= len,
+            "PBO is truncated ({} bytes of data expected, {} bytes found)",
+            data_end,
+            len
+        );
+
+        Ok(Self {
+            reader,
+            properties,
+            entries,
+            data_end,
+        })
+    }
+
+    /// The properties stored in the product entry, e.g. `prefix`.
+    pub fn properties(&self) -> &[(String, String)] {
+        &self.properties
+    }
+
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The path prefix of the files in this PBO.
+    pub fn prefix(&self) -> Option<&str> {
+        self.property("prefix")
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Find an entry by name. Names are compared case-insensitively and `/` is treated like `\`.
+    pub fn entry(&self, name: &str) -> Option<&Entry> {
+        let name = name.replace('/', "\\");
+
+        self.entries
+            .iter()
+            .find(|e| e.name.eq_ignore_ascii_case(&name))
+    }
+
+    /// Read and decompress the data of a file in this PBO.
+    pub fn read_file(&mut self, name: &str) -> Result<Vec<u8>> {
+        let entry = self
+            .entry(name)
+            .cloned()
+            .ok_or_else(|| PboError::MissingEntry(name.to_owned()))?;
+
+        self.read_data(&entry)
+    }
+
+    fn read_data(&mut self, entry: &Entry) -> Result<Vec<u8>> {
+        let mut data = vec![0; entry.data_size as usize];
+
+        self.reader
+            .seek(SeekFrom::Start(entry.offset))
+            .and_then(|_| self.reader.read_exact(&mut data))
+            .with_context(|| PboError::Entry(entry.name.clone()))?;
+
+        match entry.method {
+            PackingMethod::Compressed => decompress(&data, entry.original_size as usize)
+                .ok_or_else(|| PboError::Decompress(entry.name.clone()).into()),
+            PackingMethod::Encrypted => Err(PboError::Encrypted(entry.name.clone()).into()),
+            _ => Ok(data),
+        }
+    }
+
+    /// Extract all files into the directory `target`.
+    pub fn extract<P>(&mut self, target: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let target = target.as_ref();
+
+        for entry in self.entries.clone() {
+            let path = target.join(
+                entry
+                    .path()
+                    .ok_or_else(|| PboError::UnsafePath(entry.name.clone()))?,
+            );
+
+            debug!("Extracting {} to {}", entry.name, path.display());
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).context("Could not create directory")?;
+            }
+
+            let data = self.read_data(&entry)?;
+            fs::write(&path, data)
+                .with_context(|| format!("Could not write {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the SHA1 checksum at the end of the PBO.
+    ///
+    /// Returns `None` if the PBO has no checksum.
+    pub fn verify(&mut self) -> Result<Option<bool>> {
+        let len = self
+            .reader
+            .seek(SeekFrom::End(0))
+            .context("Could not read checksum")?;
+
+        if len != self.data_end + 21 {
+            return Ok(None);
+        }
+
+        let mut writer = HashingWriter {
+            inner: io::sink(),
+            hasher: Sha1::new(),
+        };
+        self.reader
+            .seek(SeekFrom::Start(0))
+            .context("Could not read PBO")?;
+        io::copy(&mut (&mut self.reader).take(self.data_end), &mut writer)
+            .context("Could not read PBO")?;
+
+        let mut trailer = [0; 21];
+        self.reader
+            .read_exact(&mut trailer)
+            .context("Could not read checksum")?;
+
+        Ok(Some(
+            trailer[0] == 0 && trailer[1..] == writer.hasher.finalize()[..],
+        ))
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut buf = Vec::new();
+    let mut byte = [0];
+
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == 0 {
+            break;
+        }
+        buf.push(byte[0]);
+    }
+
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn read_entry<R: Read>(reader: &mut R) -> io::Result<Entry> {
+    Ok(Entry {
+        name: read_string(reader)?,
+        method: read_u32(reader)?.into(),
+        original_size: read_u32(reader)?,
+        reserved: read_u32(reader)?,
+        timestamp: read_u32(reader)?,
+        data_size: read_u32(reader)?,
+        offset: 0,
+    })
+}
+
+/// Decompress the LZSS compressed data of an entry.
+///
+/// Returns `None` if the data is truncated or the checksum does not match.
+fn decompress(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    // `size` comes from the header and can not be trusted, a block of a flag byte and eight back
+    // references expands 17 bytes to at most 144
+    let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(9)));
+    let mut input = data.iter().copied();
+
+    'outer: while out.len() < size {
+        let flags = input.next()?;
+
+        for bit in 0..8 {
+            if out.len() >= size {
+                break 'outer;
+            }
+
+            if flags & (1 << bit) != 0 {
+                out.push(input.next()?);
+            } else {
+                let low = usize::from(input.next()?);
+                let high = usize::from(input.next()?);
+                let offset = low | ((high & 0xf0) << 4);
+                let length = (high & 0x0f) + 3;
+
+                if offset == 0 {
+                    return None;
+                }
+
+                let start = out.len() as isize - offset as isize;
+
+                for pos in start..start + length as isize {
+                    // references before the start of the data are filled with spaces
+                    let byte = if pos < 0 {
+                        b' '
+                    } else {
+                        *out.get(pos as usize)?
+                    };
+                    out.push(byte);
+                }
+            }
+        }
+    }
+
+    out.truncate(size);
+
+    let checksum = input.by_ref().take(4).collect::<Vec<u8>>();
+    let expected = out
+        .iter()
+        .fold(0u32, |acc, &b| acc.wrapping_add(u32::from(b)));
+
+    if checksum.len() == 4 && checksum[..] != expected.to_le_bytes() {
+        return None;
+    }
+
+    Some(out)
+}
+
+/// Writes everything to the inner writer and the hasher.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Builder for a new PBO.
+///
+/// Files are stored uncompressed.
+#[derive(Default)]
+pub struct PboWriter {
+    properties: Vec<(String, String)>,
+    files: Vec<(String, u32, Vec<u8>)>,
+}
+
+impl PboWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn property(&mut self, key: &str, value: &str) -> &mut Self {
+        self.properties.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Add a file. `name` is the path inside the PBO, `/` is replaced with `\`.
+    pub fn add_file(&mut self, name: &str, timestamp: u32, data: Vec<u8>) -> &mut Self {
+        self.files.push((name.replace('/', "\\"), timestamp, data));
+        self
+    }
+
+    /// Add all files in the directory `path`.
+    pub fn add_dir<P>(&mut self, path: P) -> Result<&mut Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let iter = WalkDir::new(path)
+            .min_depth(1)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()));
+
+        for entry in iter {
+            let entry = entry.context("Could not get path")?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let name = entry
+                .path()
+                .strip_prefix(path)
+                .context("Path is not inside the root directory")?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("\\");
+            let timestamp = entry
+                .metadata()
+                .ok()
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as u32)
+                .unwrap_or(0);
+            let data = fs::read(entry.path())
+                .with_context(|| format!("Could not read {}", entry.path().display()))?;
+
+            self.add_file(&name, timestamp, data);
+        }
+
+        Ok(self)
+    }
+
+    /// Write the PBO including the SHA1 checksum.
+    pub fn write<W>(&self, writer: W) -> Result<()>
+    where
+        W: Write,
+    {
+        let mut writer = HashingWriter {
+            inner: writer,
+            hasher: Sha1::new(),
+        };
+
+        self.write_content(&mut writer).context(PboError::Write)?;
+
+        let hash = writer.hasher.finalize();
+        let mut writer = writer.inner;
+        writer
+            .write_all(&[0])
+            .and_then(|_| writer.write_all(&hash))
+            .and_then(|_| writer.flush())
+            .context(PboError::Write)
+    }
+
+    /// Write the PBO to the file at `path`.
+    pub fn write_file<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::create(path).context("Could not create PBO")?;
+
+        self.write(BufWriter::new(file))
+    }
+
+    fn write_content<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if !self.properties.is_empty() {
+            write_entry(writer, "", PackingMethod::Product, 0, 0, 0)?;
+
+            for (key, value) in &self.properties {
+                write_string(writer, key)?;
+                write_string(writer, value)?;
+            }
+            write_string(writer, "")?;
+        }
+
+        for (name, timestamp, data) in &self.files {
+            let size = data.len() as u32;
+            write_entry(
+                writer,
+                name,
+                PackingMethod::Uncompressed,
+                size,
+                *timestamp,
+                size,
+            )?;
+        }
+
+        write_entry(writer, "", PackingMethod::Uncompressed, 0, 0, 0)?;
+
+        for (_, _, data) in &self.files {
+            writer.write_all(data)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_string<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    writer.write_all(s.as_bytes())?;
+    writer.write_all(&[0])
+}
+
+fn write_entry<W: Write>(
+    writer: &mut W,
+    name: &str,
+    method: PackingMethod,
+    original_size: u32,
+    timestamp: u32,
+    data_size: u32,
+) -> io::Result<()> {
+    write_string(writer, name)?;
+
+    for n in &[
+        u32::from(method),
+        // the original size is only set for compressed entries
+        if method == PackingMethod::Compressed {
+            original_size
+        } else {
+            0
+        },
+        0,
+        timestamp,
+        data_size,
+    ] {
+        writer.write_all(&n.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::tempdir;
+
+    fn sample() -> Vec<u8> {
+        let mut buf = Vec::new();
+        PboWriter::new()
+            .property("prefix", "x\\test")
+            .add_file("config.cpp", 1, b"class CfgPatches {};".to_vec())
+            .add_file("data/Readme.txt", 2, b"hello".to_vec())
+            .write(&mut buf)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut pbo = Pbo::read(Cursor::new(sample())).unwrap();
+
+        assert_eq!(pbo.prefix(), Some("x\\test"));
+        assert_eq!(
+            pbo.entries()
+                .iter()
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["config.cpp", "data\\Readme.txt"]
+        );
+        assert_eq!(pbo.read_file("data/readme.txt").unwrap(), b"hello");
+        assert_eq!(pbo.verify().unwrap(), Some(true));
+    }
+
+    #[test]
+    fn test_corrupt_checksum() {
+        let mut data = sample();
+        let len = data.len();
+        data[len - 1] ^= 0xff;
+
+        let mut pbo = Pbo::read(Cursor::new(data)).unwrap();
+        assert_eq!(pbo.verify().unwrap(), Some(false));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = sample();
+
+        assert!(Pbo::read(Cursor::new(&data[..data.len() - 30])).is_err());
+    }
+
+    #[test]
+    fn test_extract() {
+        let dir = tempdir().unwrap();
+        let mut pbo = Pbo::read(Cursor::new(sample())).unwrap();
+
+        pbo.extract(dir.path()).unwrap();
+
+        assert_eq!(
+            fs::read(dir.path().join("data").join("Readme.txt")).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn test_decompress() {
+        // "abcabcabc": three literals followed by a back reference of length 6 at offset 3
+        let data = [0b0000_0111, b'a', b'b', b'c', 0x03, 0x03];
+        let expected = b"abcabcabc";
+        let checksum = expected
+            .iter()
+            .fold(0u32, |acc, &b| acc + u32::from(b))
+            .to_le_bytes();
+
+        assert_eq!(
+            decompress(&[&data[..], &checksum[..]].concat(), expected.len()),
+            Some(expected.to_vec())
+        );
+        assert_eq!(decompress(&data, expected.len() + 10), None);
+
+        // a back reference with offset 0 points at the byte being written
+        assert_eq!(decompress(&[0, 0, 0], 3), None);
+        assert_eq!(decompress(&[0, 0, 0], usize::MAX), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! Detection of port conflicts between servers.
+//!
+//! Besides the game port a server uses the next four ports: Steam query (port + 1), Steam
+//! (port + 2), VON (port + 3) and BattlEye (port + 4).
+
+use crate::{settings::Instance, Settings};
+use anyhow::{Context, Result};
+use std::{net::UdpSocket, ops::RangeInclusive};
+use thiserror::Error;
+
+/// The number of ports a server uses starting with the game port.
+pub const PORT_COUNT: u16 = 5;
+
+/// The step between suggested game ports so the ranges of servers do not overlap.
+const SUGGESTION_STEP: u16 = 100;
+
+#[derive(Debug, Error)]
+pub enum PortError {
+    #[error("Port {0} leaves no room for the ports after it")]
+    TooHigh(u16),
+
+    #[error("Ports {ports} of {name} overlap with instance {other} using {other_ports}")]
+    Claimed {
+        name: String,
+        ports: String,
+        other: String,
+        other_ports: String,
+    },
+
+    #[error("Ports {ports:?} of {name} are already in use, try port {suggestion}")]
+    InUse {
+        name: String,
+        ports: Vec<u16>,
+        suggestion: u16,
+    },
+}
+
+/// Get the ports a server with game port `port` uses.
+pub fn ports(port: u16) -> Result<RangeInclusive<u16>> {
+    let last = port
+        .checked_add(PORT_COUNT - 1)
+        .ok_or(PortError::TooHigh(port))?;
+
+    Ok(port..=last)
+}
+
+fn format_ports(ports: &RangeInclusive<u16>) -> String {
+    format!("{}-{}", ports.start(), ports.end())
+}
+
+/// Whether the servers with game ports `a` and `b` share a port.
+pub fn overlap(a: u16, b: u16) -> bool {
+    a.max(b) - a.min(b) < PORT_COUNT
+}
+
+/// Get the ports starting with `port` that can not be bound on the host.
+pub fn in_use(port: u16) -> Result<Vec<u16>> {
+    Ok(ports(port)?
+        .filter(|&p| UdpSocket::bind(("0.0.0.0", p)).is_err())
+        .collect())
+}
+
+/// Check that the ports of `instance` are not claimed by another instance in the config and are
+/// free on the host.
+pub fn check(settings: &Settings, instance: &Instance) -> Result<()> {
+    let port = instance.options.port.unwrap_or(2302);
+    let own_ports = ports(port)?;
+
+    let mut claimed = Vec::new();
+
+    for name in settings.get_instance_names()? {
+        if name == instance.name {
+            continue;
+        }
+
+        let other = settings
+            .get_instance(Some(&name), None)
+            .with_context(|| format!("Could not get instance {}", name))?;
+        let other_port = other.options.port.unwrap_or(2302);
+        claimed.push(other_port);
+
+        if overlap(port, other_port) {
+            return Err(PortError::Claimed {
+                name: instance.name.clone(),
+                ports: format_ports(&own_ports),
+                other: name,
+                other_ports: format_ports(&ports(other_port)?),
+            }
+            .into());
+        }
+    }
+
+    let used = in_use(port)?;
+    if !used.is_empty() {
+        claimed.push(port);
+
+        return Err(PortError::InUse {
+            name: instance.name.clone(),
+            ports: used,
+            suggestion: suggest(&claimed),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Suggest a game port from 2302 upwards that neither overlaps with `claimed` nor is in use.
+fn suggest(claimed: &[u16]) -> u16 {
+    (2302..u16::MAX - PORT_COUNT)
+        .step_by(usize::from(SUGGESTION_STEP))
+        .find(|&port| {
+            claimed.iter().all(|&other| !overlap(port, other))
+                && in_use(port).map(|used| used.is_empty()).unwrap_or(false)
+        })
+        .unwrap_or(2302)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ports() {
+        assert_eq!(ports(2302).unwrap(), 2302..=2306);
+        assert!(ports(u16::MAX - 2).is_err());
+
+        assert!(overlap(2302, 2302));
+        assert!(overlap(2302, 2306));
+        assert!(overlap(2306, 2302));
+        assert!(!overlap(2302, 2307));
+    }
+
+    #[test]
+    fn test_in_use() {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let start = port.min(u16::MAX - PORT_COUNT + 1);
+
+        assert!(in_use(start).unwrap().contains(&port));
+        assert_ne!(suggest(&[2302]), 2302);
+    }
+}
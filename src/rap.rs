@@ -0,0 +1,451 @@
+//! Reader and writer for binarized ("rapified") Arma configs like `config.bin` or a binarized
+//! `mission.sqm`.
+//!
+//! A rapified config starts with the signature `\0raP` followed by the body of the root class.
+//! Each class body starts with the name of its parent class and the number of entries. The
+//! bodies of nested classes are stored elsewhere in the file and referenced by their offset.
+
+use crate::arma_config::{Cfg, Value};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
+
+pub const SIGNATURE: &[u8] = b"\0raP";
+
+/// Whether `data` is a rapified config.
+pub fn is_rapified(data: &[u8]) -> bool {
+    data.starts_with(SIGNATURE)
+}
+
+/// Parse a rapified config.
+pub fn from_bytes(data: &[u8]) -> Result<Cfg> {
+    ensure!(is_rapified(data), "Data is not a rapified config");
+    ensure!(data.len() >= 16, "Rapified config is too short");
+
+    let mut reader = Reader {
+        data,
+        pos: 16,
+        depth: 0,
+    };
+    let root = reader
+        .class_body()
+        .context("Could not read rapified config")?;
+
+    Ok(Cfg(Value::Class(root)))
+}
+
+/// Rapify a config.
+///
+/// Booleans are written as numbers and floats lose precision since rapified configs only
+/// support 32 bit floats.
+pub fn to_bytes(config: &Cfg) -> Result<Vec<u8>> {
+    let root = config
+        .root()
+        .as_class()
+        .context("Config root is not a class")?;
+
+    let mut writer = Writer {
+        data: SIGNATURE.to_vec(),
+    };
+    writer.u32(0);
+    writer.u32(8);
+    // offset of the enum table, patched below
+    writer.u32(0);
+
+    writer
+        .class(root)
+        .context("Could not write rapified config")?;
+
+    let enum_offset = writer.offset()?;
+    writer.patch(12, enum_offset);
+    // no enums
+    writer.u32(0);
+
+    Ok(writer.data)
+}
+
+/// Nesting depth after which a config is considered corrupt, e.g. because of a reference loop.
+const MAX_DEPTH: usize = 64;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Result<&[u8]> {
+        let end = self.pos + n;
+        ensure!(end <= self.data.len(), "Unexpected end of data");
+
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    /// A variable length integer with 7 bits per byte, least significant group first.
+    fn compressed_int(&mut self) -> Result<usize> {
+        let mut n = 0;
+
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            n |= usize::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+
+        bail!("Compressed integer is too long")
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self
+            .data
+            .get(self.pos..)
+            .context("Unexpected end of data")?
+            .iter()
+            .position(|&b| b == 0)
+            .context("Unterminated string")?;
+        let s = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.pos += 1;
+        Ok(s)
+    }
+
+    fn class_body(&mut self) -> Result<HashMap<String, Value>> {
+        let _parent = self.string()?;
+        let count = self.compressed_int()?;
+        let mut map = HashMap::new();
+
+        for _ in 0..count {
+            match self.u8()? {
+                0 => {
+                    let name = self.string()?;
+                    let offset = self.u32()? as usize;
+                    ensure!(offset < self.data.len(), "Class offset is out of bounds");
+
+                    ensure!(self.depth < MAX_DEPTH, "Classes are nested too deep");
+
+                    let pos = self.pos;
+                    self.pos = offset;
+                    self.depth += 1;
+                    let body = self
+                        .class_body()
+                        .with_context(|| format!("Could not read class {}", name))?;
+                    self.depth -= 1;
+                    self.pos = pos;
+
+                    map.insert(name, Value::Class(body));
+                }
+                1 => {
+                    let typ = self.u8()?;
+                    let name = self.string()?;
+                    let value = self.value(typ)?;
+                    map.insert(name, value);
+                }
+                2 => {
+                    let name = self.string()?;
+                    let value = self.array()?;
+                    map.insert(name, value);
+                }
+                // external class reference
+                3 => {
+                    let name = self.string()?;
+                    map.entry(name)
+                        .or_insert_with(|| Value::Class(HashMap::new()));
+                }
+                // delete statement
+                4 => {
+                    self.string()?;
+                }
+                // array with `+=`
+                5 => {
+                    let _flags = self.u32()?;
+                    let name = self.string()?;
+                    let value = self.array()?;
+                    map.insert(name, value);
+                }
+                typ => bail!("Unknown entry type {}", typ),
+            }
+        }
+
+        Ok(map)
+    }
+
+    fn value(&mut self, typ: u8) -> Result<Value> {
+        Ok(match typ {
+            // strings and variables
+            0 | 4 => Value::String(self.string()?),
+            1 => Value::Float(f64::from(self.f32()?)),
+            2 => Value::Number(i64::from(self.i32()?)),
+            3 => self.array()?,
+            6 => Value::Number(self.i64()?),
+            typ => bail!("Unknown value type {}", typ),
+        })
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        let count = self.compressed_int()?;
+
+        (0..count)
+            .map(|_| {
+                let typ = self.u8()?;
+                self.value(typ)
+            })
+            .collect::<Result<Vec<Value>>>()
+            .map(Value::List)
+    }
+}
+
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn offset(&self) -> Result<u32> {
+        u32::try_from(self.data.len()).context("Config is too large")
+    }
+
+    fn patch(&mut self, pos: usize, n: u32) {
+        self.data[pos..pos + 4].copy_from_slice(&n.to_le_bytes());
+    }
+
+    fn u8(&mut self, n: u8) {
+        self.data.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.data.extend(&n.to_le_bytes());
+    }
+
+    fn compressed_int(&mut self, mut n: usize) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+
+            if n == 0 {
+                self.u8(byte);
+                return;
+            }
+
+            self.u8(byte | 0x80);
+        }
+    }
+
+    fn string(&mut self, s: &str) -> Result<()> {
+        ensure!(!s.contains('\0'), "String {:?} contains a null byte", s);
+
+        self.data.extend(s.as_bytes());
+        self.u8(0);
+        Ok(())
+    }
+
+    /// Write a class body followed by the bodies of its nested classes.
+    fn class(&mut self, map: &HashMap<String, Value>) -> Result<()> {
+        let mut names = map.keys().collect::<Vec<&String>>();
+        names.sort();
+
+        // no parent class
+        self.string("")?;
+        self.compressed_int(names.len());
+
+        let mut classes = Vec::new();
+
+        for name in names {
+            match &map[name] {
+                Value::Class(body) => {
+                    self.u8(0);
+                    self.string(name)?;
+                    classes.push((self.data.len(), body));
+                    // offset of the class body, patched below
+                    self.u32(0);
+                }
+                Value::List(list) => {
+                    self.u8(2);
+                    self.string(name)?;
+                    self.array(list)
+                        .with_context(|| format!("Could not write array {}", name))?;
+                }
+                value => {
+                    self.u8(1);
+                    self.u8(Self::value_type(value));
+                    self.string(name)?;
+                    self.value(value)?;
+                }
+            }
+        }
+
+        for (pos, body) in classes {
+            let offset = self.offset()?;
+            self.patch(pos, offset);
+            self.class(body)?;
+        }
+
+        Ok(())
+    }
+
+    fn value_type(value: &Value) -> u8 {
+        match value {
+            Value::String(_) => 0,
+            Value::Float(_) => 1,
+            Value::Number(n) if i32::try_from(*n).is_err() => 6,
+            Value::Number(_) | Value::Boolean(_) => 2,
+            Value::List(_) => 3,
+            // classes are not allowed as values and rejected when writing
+            Value::Class(_) => 255,
+        }
+    }
+
+    fn value(&mut self, value: &Value) -> Result<()> {
+        match value {
+            Value::String(s) => self.string(s)?,
+            Value::Float(f) => self.data.extend(&(*f as f32).to_le_bytes()),
+            Value::Number(n) => match i32::try_from(*n) {
+                Ok(n) => self.data.extend(&n.to_le_bytes()),
+                Err(_) => self.data.extend(&n.to_le_bytes()),
+            },
+            Value::Boolean(b) => self.data.extend(&i32::from(*b).to_le_bytes()),
+            Value::List(list) => self.array(list)?,
+            Value::Class(_) => bail!("Classes are not allowed inside arrays"),
+        }
+
+        Ok(())
+    }
+
+    fn array(&mut self, list: &[Value]) -> Result<()> {
+        self.compressed_int(list.len());
+
+        for value in list {
+            self.u8(Self::value_type(value));
+            self.value(value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_bytes() {
+        let mut data = SIGNATURE.to_vec();
+        data.extend(&[0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0]);
+        // root: parent, 2 entries
+        data.extend(b"\0\x02");
+        // class CfgPatches at offset 53
+        data.extend(b"\x00CfgPatches\0");
+        data.extend(&53u32.to_le_bytes());
+        // addons[] = {"ace_main"};
+        data.extend(b"\x02addons\0\x01\x00ace_main\0");
+        assert_eq!(data.len(), 53);
+        // CfgPatches: parent, 1 entry: version = 3;
+        data.extend(b"\0\x01\x01\x02version\0");
+        data.extend(&3i32.to_le_bytes());
+
+        let config = from_bytes(&data).unwrap();
+
+        assert_eq!(
+            config.root().get_path("CfgPatches/version"),
+            Some(&Value::Number(3))
+        );
+        assert_eq!(
+            config.root().get("addons"),
+            Some(&Value::List(vec![Value::String("ace_main".into())]))
+        );
+    }
+
+    #[test]
+    fn test_reference_loop() {
+        let mut data = SIGNATURE.to_vec();
+        data.extend(&[0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0]);
+        // root contains a class whose body is the root again
+        data.extend(b"\0\x01\x00loop\0");
+        data.extend(&16u32.to_le_bytes());
+
+        assert!(from_bytes(&data).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(from_bytes(b"\0raP").is_err());
+        assert!(from_bytes(&SIGNATURE.repeat(4)).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let config = Cfg::from_string(
+            r#"
+class CfgPatches {
+    class ace_main {
+        units[] = {};
+        requiredVersion = 2.0;
+        requiredAddons[] = {"cba_main", "A3_Data_F"};
+        nested[] = {1, {"two", 3.5}};
+        big = 5000000000;
+    };
+};
+class Empty {};
+author = "ACE ""Team""";
+"#,
+        )
+        .unwrap();
+
+        let data = to_bytes(&config).unwrap();
+        assert!(is_rapified(&data));
+
+        let enum_offset = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+        assert_eq!(enum_offset, data.len() - 4);
+
+        assert_eq!(from_bytes(&data).unwrap().0, config.0);
+    }
+
+    #[test]
+    fn test_to_bytes_rejects_class_in_array() {
+        let mut root = HashMap::new();
+        root.insert(
+            "list".to_owned(),
+            Value::List(vec![Value::Class(HashMap::new())]),
+        );
+
+        assert!(to_bytes(&Cfg(Value::Class(root))).is_err());
+    }
+
+    #[test]
+    fn test_compressed_int() {
+        for &n in &[0, 1, 0x7f, 0x80, 0x3fff, 0x4000, 1_000_000] {
+            let mut writer = Writer { data: Vec::new() };
+            writer.compressed_int(n);
+
+            let mut reader = Reader {
+                data: &writer.data,
+                pos: 0,
+                depth: 0,
+            };
+            assert_eq!(reader.compressed_int().unwrap(), n);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Client for the BattlEye RCon protocol.
+//!
+//! Every packet starts with `BE`, the CRC32 of the rest of the packet and `0xFF`, followed by the
+//! packet type and its data. After logging in with the password from `beserver_x64.cfg`, commands
+//! are sent with a sequence number the server echoes in its response. Messages the server sends
+//! on its own have to be acknowledged or the server drops the connection.
+
+use anyhow::{ensure, Context, Result};
+use std::{
+    convert::TryInto,
+    net::{ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+use thiserror::Error;
+
+const LOGIN: u8 = 0x00;
+const COMMAND: u8 = 0x01;
+const MESSAGE: u8 = 0x02;
+
+/// How long to wait for a response of the server.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum RConError {
+    #[error("Invalid RCon packet")]
+    InvalidPacket,
+
+    #[error("RCon packet checksum does not match")]
+    Checksum,
+
+    #[error("RCon login failed, check the password")]
+    Login,
+
+    #[error("RCon server did not respond")]
+    Timeout,
+}
+
+/// Build a packet of `kind` containing `data`.
+fn packet(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut payload = vec![0xff, kind];
+    payload.extend(data);
+
+    let mut packet = b"BE".to_vec();
+    packet.extend(&crc32fast::hash(&payload).to_le_bytes());
+    packet.extend(payload);
+    packet
+}
+
+/// Check the header and checksum of a packet and get its type and data.
+fn parse(packet: &[u8]) -> Result<(u8, &[u8])> {
+    ensure!(
+        packet.len() >= 8 && packet.starts_with(b"BE") && packet[6] == 0xff,
+        RConError::InvalidPacket
+    );
+
+    let checksum = u32::from_le_bytes(packet[2..6].try_into()?);
+    ensure!(
+        checksum == crc32fast::hash(&packet[6..]),
+        RConError::Checksum
+    );
+
+    Ok((packet[7], &packet[8..]))
+}
+
+pub struct RCon {
+    socket: UdpSocket,
+    sequence: u8,
+}
+
+impl RCon {
+    /// Connect to the RCon server at `address` and log in.
+    pub fn connect<A>(address: A, password: &str) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let socket = UdpSocket::bind("0.0.0.0:0").context("Could not bind RCon socket")?;
+        socket
+            .connect(address)
+            .context("Could not connect to RCon server")?;
+        socket
+            .set_read_timeout(Some(TIMEOUT))
+            .context("Could not set RCon timeout")?;
+
+        let rcon = Self {
+            socket,
+            sequence: 0,
+        };
+
+        rcon.send(LOGIN, password.as_bytes())?;
+        let data = rcon.receive(LOGIN)?;
+        ensure!(data.first() == Some(&1), RConError::Login);
+
+        Ok(rcon)
+    }
+
+    /// Run a command like `#shutdown` or `players` and get its output.
+    pub fn command(&mut self, command: &str) -> Result<String> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut data = vec![sequence];
+        data.extend(command.as_bytes());
+        self.send(COMMAND, &data)?;
+
+        // long responses are split into several packets: 0x00, the number of parts and the index
+        let mut parts: Vec<Option<Vec<u8>>> = Vec::new();
+
+        loop {
+            let data = self.receive(COMMAND)?;
+            if data.first() != Some(&sequence) {
+                continue;
+            }
+
+            match data[1..] {
+                [0x00, count, index, ..] => {
+                    if parts.is_empty() {
+                        parts = vec![None; usize::from(count)];
+                    }
+                    if let Some(part) = parts.get_mut(usize::from(index)) {
+                        *part = Some(data[4..].to_vec());
+                    }
+                    if parts.iter().all(Option::is_some) {
+                        let output = parts.into_iter().flatten().flatten().collect::<Vec<u8>>();
+                        return Ok(String::from_utf8_lossy(&output).into_owned());
+                    }
+                }
+                _ => return Ok(String::from_utf8_lossy(&data[1..]).into_owned()),
+            }
+        }
+    }
+
+    /// Send a message to all players.
+    pub fn say(&mut self, message: &str) -> Result<()> {
+        self.command(&format!("say -1 {}", message))?;
+        Ok(())
+    }
+
+    fn send(&self, kind: u8, data: &[u8]) -> Result<()> {
+        self.socket
+            .send(&packet(kind, data))
+            .context("Could not send RCon packet")?;
+        Ok(())
+    }
+
+    /// Receive the next packet of `kind`, acknowledging server messages on the way.
+    fn receive(&self, kind: u8) -> Result<Vec<u8>> {
+        let mut buf = [0; 4096];
+
+        loop {
+            let len = self.socket.recv(&mut buf).context(RConError::Timeout)?;
+            let (received, data) = parse(&buf[..len])?;
+
+            if received == MESSAGE {
+                if let Some(&sequence) = data.first() {
+                    self.send(MESSAGE, &[sequence])?;
+                }
+                continue;
+            }
+
+            if received == kind {
+                return Ok(data.to_vec());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_packet() {
+        let login = packet(LOGIN, b"secret");
+        assert_eq!(parse(&login).unwrap(), (LOGIN, &b"secret"[..]));
+
+        let mut corrupt = login;
+        corrupt[9] ^= 1;
+        assert!(parse(&corrupt).is_err());
+        assert!(parse(b"BE").is_err());
+    }
+
+    #[test]
+    fn test_command() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 4096];
+
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(parse(&buf[..len]).unwrap(), (LOGIN, &b"secret"[..]));
+            server.send_to(&packet(LOGIN, &[1]), client).unwrap();
+
+            let (len, _) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(parse(&buf[..len]).unwrap(), (COMMAND, &b"\0players"[..]));
+
+            // a server message in between has to be acknowledged
+            server
+                .send_to(&packet(MESSAGE, b"\x07hello"), client)
+                .unwrap();
+            server
+                .send_to(&packet(COMMAND, b"\0\0\x02\x01 list"), client)
+                .unwrap();
+            server
+                .send_to(&packet(COMMAND, b"\0\0\x02\x00Player"), client)
+                .unwrap();
+
+            let (len, _) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(parse(&buf[..len]).unwrap(), (MESSAGE, &b"\x07"[..]));
+        });
+
+        let mut rcon = RCon::connect(address, "secret").unwrap();
+        assert_eq!(rcon.command("players").unwrap(), "Player list");
+
+        handle.join().unwrap();
+    }
+}
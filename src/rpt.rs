@@ -0,0 +1,361 @@
+//! Analysis of the RPT logs of the server.
+//!
+//! Lines start with a timestamp depending on `timeStampFormat` of the server config, either
+//! ` 1:23:45`, `2020/08/18, 13:37:00` or nothing at all, which is stripped before matching.
+
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::BufRead,
+};
+
+/// A mod from the list of mods the server prints on startup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedMod {
+    pub name: String,
+    pub version: Option<String>,
+    /// The directory of the mod, like `@CBA_A3` or `enoch` for DLCs.
+    pub dir: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MissingAddon {
+    pub addon: String,
+    pub required_by: String,
+}
+
+/// A script error with the number of times it occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub file: String,
+    pub line: u32,
+    /// The message of the first occurrence.
+    pub message: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Kick {
+    pub player: String,
+    pub reason: String,
+}
+
+/// What happened according to a RPT log.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub mods: Vec<LoadedMod>,
+    pub missing_addons: BTreeSet<MissingAddon>,
+    /// Script errors, the most frequent first.
+    pub script_errors: Vec<ScriptError>,
+    /// The number of `Server: Object not found` messages.
+    pub objects_not_found: usize,
+    /// The most `Server: Object not found` messages within a minute.
+    pub objects_not_found_peak: usize,
+    pub kicks: Vec<Kick>,
+    pub connects: usize,
+    pub disconnects: usize,
+    /// The names of all players that connected.
+    pub players: BTreeSet<String>,
+    /// The FPS of every `Server load` line.
+    pub fps: Vec<u32>,
+}
+
+/// Split the timestamp from a line.
+fn split_time(line: &str) -> (Option<&str>, &str) {
+    let trimmed = line.trim_start();
+    let is_time = |token: &str| {
+        token.matches(':').count() == 2 && token.chars().all(|c| c.is_ascii_digit() || c == ':')
+    };
+
+    // 2020/08/18, 13:37:00
+    if let Some((date, rest)) = trimmed.split_once(", ") {
+        if date.len() == 10 && date.matches('/').count() == 2 {
+            if let Some((time, message)) = rest.split_once(' ') {
+                if is_time(time) {
+                    return (Some(&trimmed[..date.len() + 2 + time.len()]), message);
+                }
+            }
+        }
+    }
+
+    // 1:23:45
+    match trimmed.split_once(' ') {
+        Some((time, message)) if is_time(time) => (Some(time), message),
+        _ => (None, line),
+    }
+}
+
+/// Get the text between the first pair of single quotes after `after`.
+fn quoted<'a>(line: &'a str, after: &str) -> Option<&'a str> {
+    let rest = &line[line.find(after)? + after.len()..];
+    let start = rest.find('\'')? + 1;
+    let end = start + rest[start..].find('\'')?;
+
+    Some(&rest[start..end])
+}
+
+/// Parse a row of the mod list like `CBA_A3 v3.15.2 | @CBA_A3 | false | ...`.
+fn parse_mod(row: &str) -> Option<LoadedMod> {
+    let mut columns = row.split('|').map(str::trim);
+    let name = columns.next()?;
+    let dir = columns.next()?;
+
+    let (name, version) = match name.rsplit_once(" v") {
+        Some((name, version)) if version.starts_with(|c: char| c.is_ascii_digit()) => {
+            (name, Some(version.to_owned()))
+        }
+        _ => (name, None),
+    };
+
+    Some(LoadedMod {
+        name: name.to_owned(),
+        version,
+        dir: dir.to_owned(),
+    })
+}
+
+/// Parse a line like `File \x\cba\addons\main\fnc_init.sqf..., line 42`.
+fn parse_file_line(message: &str) -> Option<(String, u32)> {
+    let rest = message.strip_prefix("File ")?;
+    let (file, line) = rest.rsplit_once(", line ")?;
+    let line = line.trim().parse().ok()?;
+
+    Some((file.trim_end_matches("...").to_owned(), line))
+}
+
+enum Section {
+    None,
+    ModsHeader,
+    Mods,
+}
+
+impl Report {
+    /// Analyze the lines of a RPT log.
+    pub fn parse<R>(reader: R) -> Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut report = Self::default();
+        let mut section = Section::None;
+
+        let mut errors: HashMap<(String, u32), ScriptError> = HashMap::new();
+        // the message of the error that is waiting for its file and line
+        let mut pending_error: Option<String> = None;
+        let mut not_found_per_minute: BTreeMap<String, usize> = BTreeMap::new();
+
+        for line in reader.split(b'\n') {
+            let line = line.context("Could not read log")?;
+            let line = String::from_utf8_lossy(&line);
+            let (time, message) = split_time(line.trim_end());
+
+            if message.contains("List of mods") {
+                section = Section::ModsHeader;
+                continue;
+            }
+
+            match section {
+                Section::ModsHeader => {
+                    if message.starts_with("---") {
+                        section = Section::Mods;
+                    }
+                    continue;
+                }
+                Section::Mods => {
+                    if message.starts_with("===") || !message.contains('|') {
+                        section = Section::None;
+                    } else {
+                        report.mods.extend(parse_mod(message));
+                    }
+                    continue;
+                }
+                Section::None => {}
+            }
+
+            if message.starts_with("Error in expression") {
+                pending_error = Some(String::new());
+            } else if let Some(error) = message.trim_start().strip_prefix("Error ") {
+                match &mut pending_error {
+                    Some(pending) if pending.is_empty() && !error.starts_with("position:") => {
+                        *pending = error.to_owned();
+                    }
+                    _ => {}
+                }
+            } else if let Some((file, line)) = parse_file_line(message) {
+                if let Some(message) = pending_error.take() {
+                    errors
+                        .entry((file.clone(), line))
+                        .or_insert_with(|| ScriptError {
+                            file,
+                            line,
+                            message,
+                            count: 0,
+                        })
+                        .count += 1;
+                }
+            } else if message.contains("requires addon") {
+                if let (Some(required_by), Some(addon)) =
+                    (quoted(message, "Addon"), quoted(message, "requires addon"))
+                {
+                    report.missing_addons.insert(MissingAddon {
+                        addon: addon.to_owned(),
+                        required_by: required_by.to_owned(),
+                    });
+                }
+            } else if message.contains("Server: Object ") && message.contains(" not found") {
+                report.objects_not_found += 1;
+
+                if let Some(time) = time {
+                    // the minute without the seconds
+                    let minute = time.rsplit_once(':').map_or(time, |(minute, _)| minute);
+                    *not_found_per_minute.entry(minute.to_owned()).or_default() += 1;
+                }
+            } else if let Some((player, reason)) = message
+                .strip_prefix("Player ")
+                .and_then(|rest| rest.split_once(" kicked off by BattlEye: "))
+            {
+                report.kicks.push(Kick {
+                    player: player.to_owned(),
+                    reason: reason.to_owned(),
+                });
+            } else if let Some((player, _)) = message
+                .strip_prefix("Player ")
+                .and_then(|rest| rest.split_once(" connected (id="))
+            {
+                report.connects += 1;
+                report.players.insert(player.to_owned());
+            } else if message.starts_with("Player ") && message.ends_with(" disconnected.") {
+                report.disconnects += 1;
+            } else if let Some(rest) = message
+                .find("Server load: FPS ")
+                .map(|i| &message[i + "Server load: FPS ".len()..])
+            {
+                let fps = rest.split(|c: char| !c.is_ascii_digit()).next();
+                if let Some(fps) = fps.and_then(|fps| fps.parse().ok()) {
+                    report.fps.push(fps);
+                }
+            }
+        }
+
+        report.objects_not_found_peak = not_found_per_minute.values().copied().max().unwrap_or(0);
+
+        report.script_errors = errors.into_values().collect();
+        report.script_errors.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| (&a.file, a.line).cmp(&(&b.file, b.line)))
+        });
+
+        Ok(report)
+    }
+
+    /// The lowest, average and highest server FPS.
+    pub fn fps_range(&self) -> Option<(u32, f64, u32)> {
+        let min = *self.fps.iter().min()?;
+        let max = *self.fps.iter().max()?;
+        let average =
+            self.fps.iter().map(|&fps| f64::from(fps)).sum::<f64>() / self.fps.len() as f64;
+
+        Some((min, average, max))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RPT: &str = r#"=====================================================================
+== /home/arma/arma3/arma3server_x64
+=====================================================================
+ 0:00:01 ============================== List of mods ==============================
+ 0:00:01 modsReadOnly = true
+ 0:00:01                      name |     modDir |    default | origin
+ 0:00:01 ---------------------------------------------------------------
+ 0:00:01          CBA_A3 v3.15.2.200818 |  @CBA_A3 |      false | GAME DIR
+ 0:00:01                       Arma 3 Contact |      enoch |       true | GAME DIR
+ 0:00:01 ===============================================================
+ 0:00:02 Warning Message: Addon 'ace_main' requires addon 'cba_main'
+ 0:00:02 Warning Message: Addon 'ace_main' requires addon 'cba_main'
+ 0:01:10 Player Foo connected (id=76561198000000001).
+ 0:01:12 Error in expression <_x setDamage 1>
+ 0:01:12   Error position: <_x setDamage 1>
+ 0:01:12   Error Undefined variable in expression: _x
+ 0:01:12 File mpmissions\__cur_mp.Altis\init.sqf..., line 5
+ 0:01:13 Error in expression <_x setDamage 1>
+ 0:01:13   Error Undefined variable in expression: _x
+ 0:01:13 File mpmissions\__cur_mp.Altis\init.sqf..., line 5
+ 0:01:14 Error in expression <hint 1>
+ 0:01:14   Error Type Number, expected String
+ 0:01:14 File \x\cba\addons\main\fnc_init.sqf, line 42
+ 0:02:00 Server: Object 2:345 not found (message Type_120)
+ 0:02:01 Server: Object 2:346 not found (message Type_120)
+ 0:03:00 Server: Object 2:347 not found (message Type_120)
+ 0:04:00 Server load: FPS 47, memory used: 2048 MB, out: 120 Kbps, in: 80 Kbps, Players: 1
+ 0:05:00 Server load: FPS 12, memory used: 2100 MB, out: 140 Kbps, in: 90 Kbps, Players: 1
+ 0:06:00 Player Foo kicked off by BattlEye: Client not responding
+ 0:06:01 Player Foo disconnected.
+"#;
+
+    #[test]
+    fn test_split_time() {
+        assert_eq!(split_time(" 1:23:45 Hello"), (Some("1:23:45"), "Hello"));
+        assert_eq!(
+            split_time("2020/08/18, 13:37:00 Hello"),
+            (Some("2020/08/18, 13:37:00"), "Hello")
+        );
+        assert_eq!(split_time("Hello world"), (None, "Hello world"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let report = Report::parse(RPT.as_bytes()).unwrap();
+
+        assert_eq!(
+            report.mods,
+            vec![
+                LoadedMod {
+                    name: "CBA_A3".into(),
+                    version: Some("3.15.2.200818".into()),
+                    dir: "@CBA_A3".into(),
+                },
+                LoadedMod {
+                    name: "Arma 3 Contact".into(),
+                    version: None,
+                    dir: "enoch".into(),
+                },
+            ]
+        );
+
+        assert_eq!(report.missing_addons.len(), 1);
+        let missing = report.missing_addons.iter().next().unwrap();
+        assert_eq!(missing.addon, "cba_main");
+        assert_eq!(missing.required_by, "ace_main");
+
+        assert_eq!(report.script_errors.len(), 2);
+        assert_eq!(
+            report.script_errors[0],
+            ScriptError {
+                file: r"mpmissions\__cur_mp.Altis\init.sqf".into(),
+                line: 5,
+                message: "Undefined variable in expression: _x".into(),
+                count: 2,
+            }
+        );
+        assert_eq!(report.script_errors[1].line, 42);
+
+        assert_eq!(report.objects_not_found, 3);
+        assert_eq!(report.objects_not_found_peak, 2);
+
+        assert_eq!(
+            report.kicks,
+            vec![Kick {
+                player: "Foo".into(),
+                reason: "Client not responding".into(),
+            }]
+        );
+        assert_eq!(report.connects, 1);
+        assert_eq!(report.disconnects, 1);
+        assert!(report.players.contains("Foo"));
+
+        assert_eq!(report.fps_range(), Some((12, 29.5, 47)));
+    }
+}
@@ -1,7 +1,19 @@
-use anyhow::{Context, Result};
+use crate::{
+    config::{
+        modpack::{Mod, Modpack, ModpackConfig},
+        OptionSet, OptionStack,
+    },
+    daemon::DEFAULT_NAME,
+    steamcmd::Branch,
+    util::list_mods,
+};
+use anyhow::{bail, Context, Result};
 use config::{Config, Environment, File, Value};
 use serde::Deserialize;
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -36,6 +48,16 @@ pub enum SettingsError {
 
 pub struct Settings(Config);
 
+/// A server running from the shared installation, either a `[instance.<name>]` table or just an
+/// option set.
+pub struct Instance {
+    /// The name used for PID files, logs and systemd units.
+    pub name: String,
+    pub options: OptionSet,
+    /// Where each set option came from, like `instance.public`, `options.global` or `default`.
+    pub sources: BTreeMap<String, String>,
+}
+
 impl Settings {
     pub fn from_path<P>(path: Option<P>) -> Result<Self>
     where
@@ -131,4 +153,394 @@ impl Settings {
             .context("Could not read key `server.path`")?
             .unwrap_or(String::from("./arma3")))
     }
+
+    /// Get the branch the server is installed from.
+    pub fn get_branch(&self) -> Result<Branch> {
+        Ok(self
+            .get("server.branch")
+            .context("Could not read key `server.branch`")?
+            .unwrap_or_default())
+    }
+
+    /// Whether the 64-bit server binary is used, set with `server.bits` to 32 or 64.
+    pub fn get_x64(&self) -> Result<bool> {
+        match self
+            .get_int("server.bits")
+            .context("Could not read key `server.bits`")?
+        {
+            None | Some(64) => Ok(true),
+            Some(32) => Ok(false),
+            Some(bits) => bail!("server.bits has to be 32 or 64, not {}", bits),
+        }
+    }
+
+    /// Get the global option set merged with the named option set.
+    pub fn get_option_set(&self, name: Option<&str>) -> Result<OptionSet> {
+        let mut stack = OptionStack::new();
+        self.push_option_sets(&mut stack, name)?;
+
+        Ok(stack.resolve().0)
+    }
+
+    /// Push the global option set and the named option set on `stack`.
+    fn push_option_sets(&self, stack: &mut OptionStack, name: Option<&str>) -> Result<()> {
+        if let Some(globals) = self
+            .get::<OptionSet>("options.global")
+            .context("Could not get global options")?
+        {
+            stack.push("options.global", globals);
+        }
+
+        if let Some(name) = name {
+            let key = format!("options.{}", name);
+
+            if let Some(set) = self
+                .get(&key)
+                .context("Could not get provided option set")?
+            {
+                stack.push(key, set);
+            } else {
+                bail!("Could nof find option set in config")
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the options set in `AMRAAM_OPTION_<KEY>` environment variables.
+    fn get_env_options(&self) -> Result<OptionSet> {
+        let mut env = Config::default();
+        env.merge(Environment::with_prefix("AMRAAM_OPTION"))
+            .context(SettingsError::MergeConfig)?;
+
+        env.try_into()
+            .context("Could not read options from the environment")
+    }
+
+    /// Get an instance by name or fall back to the option set.
+    ///
+    /// See [`get_instance_with`](Self::get_instance_with).
+    pub fn get_instance(
+        &self,
+        instance: Option<&str>,
+        option_set: Option<&str>,
+    ) -> Result<Instance> {
+        self.get_instance_with(instance, option_set, OptionSet::new())
+    }
+
+    /// Get an instance by name or fall back to the option set, with `overrides` from the command
+    /// line.
+    ///
+    /// An instance table takes the same keys as an option set plus `option_set`, the option set
+    /// it is based on. If there is no instance table with the name, the option set with the name
+    /// is used instead.
+    ///
+    /// The options are merged from the lowest to the highest precedence: the defaults, the
+    /// binary of `server.branch` and `server.bits` or `server.binary`, `options.global`, the
+    /// option set, the instance table, `overrides` and the `AMRAAM_OPTION_<KEY>` environment
+    /// variables. For instances `profiles` defaults to `profiles/<name>`.
+    pub fn get_instance_with(
+        &self,
+        instance: Option<&str>,
+        option_set: Option<&str>,
+        overrides: OptionSet,
+    ) -> Result<Instance> {
+        let mut stack = OptionStack::new();
+
+        // the binary can be set for the installation and changed by option sets and instances
+        stack.push(
+            "server.branch",
+            OptionSet {
+                binary: Some(
+                    self.get_branch()?
+                        .default_binary(self.get_x64()?)
+                        .to_owned(),
+                ),
+                ..OptionSet::new()
+            },
+        );
+        stack.push(
+            "server.binary",
+            OptionSet {
+                binary: self.get_str("server.binary")?,
+                ..OptionSet::new()
+            },
+        );
+
+        let name = match instance {
+            Some(name) => {
+                let key = format!("instance.{}", name);
+
+                match self
+                    .get::<OptionSet>(&key)
+                    .context("Could not get instance")?
+                {
+                    Some(options) => {
+                        let option_set = match self
+                            .get_str(&format!("{}.option_set", key))
+                            .context("Could not get option set of instance")?
+                        {
+                            Some(option_set) => Some(option_set),
+                            None => option_set.map(str::to_owned),
+                        };
+
+                        // instances share the installation, so they need their own logs and
+                        // profiles
+                        stack.push(
+                            "default",
+                            OptionSet {
+                                profiles: Some(format!("profiles/{}", name)),
+                                ..OptionSet::new()
+                            },
+                        );
+                        self.push_option_sets(&mut stack, option_set.as_deref())?;
+                        stack.push(key, options);
+                    }
+                    None if option_set.is_none()
+                        && self.get_table(&format!("options.{}", name))?.is_some() =>
+                    {
+                        self.push_option_sets(&mut stack, Some(name))?;
+                    }
+                    None => bail!("Could not find instance {} in config", name),
+                }
+
+                name
+            }
+            None => {
+                self.push_option_sets(&mut stack, option_set)?;
+                option_set.unwrap_or(DEFAULT_NAME)
+            }
+        };
+
+        stack.push("command line", overrides);
+        stack.push("environment", self.get_env_options()?);
+
+        let (options, sources) = stack.resolve();
+
+        Ok(Instance {
+            name: name.to_owned(),
+            options,
+            sources,
+        })
+    }
+
+    /// Get the names of all instances.
+    pub fn get_instance_names(&self) -> Result<Vec<String>> {
+        let mut names = self
+            .get_table("instance")
+            .context("Could not get instances from config")?
+            .unwrap_or_default()
+            .into_keys()
+            .collect::<Vec<String>>();
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Get the mods of a modpack without the mods of inherited modpacks.
+    pub fn get_modpack(&self, name: &str) -> Result<Modpack> {
+        let modpack_config: ModpackConfig = self
+            .get(&format!("modpack.{}", name))
+            .context("Could not get modpack from config")?
+            .context("Missing modpack config entry")?;
+
+        modpack_config.as_modpack()
+    }
+
+    /// Get the mods of a modpack followed by the mods of the modpacks it inherits from, following
+    /// the whole chain. Every modpack is loaded once, even if it is inherited multiple times.
+    ///
+    /// Fails if a mod is not installed in the server at `server_path`.
+    pub fn get_modpack_mods(&self, name: &str, server_path: &str) -> Result<Vec<Mod>> {
+        let installed_mods = list_mods(Path::new(server_path).join("mods"))?;
+        let mut loaded = Vec::new();
+        let mut mods = Vec::new();
+        self.collect_modpacks(name, &mut loaded, &mut mods)?;
+
+        let missing_mods = mods
+            .iter()
+            .filter(|m| !installed_mods.contains(&m.name))
+            .map(|m| m.name.clone())
+            .collect::<Vec<String>>();
+
+        if !missing_mods.is_empty() {
+            bail!(
+                "Cannot run server since some mods are missing:\n{}",
+                missing_mods.join("\n")
+            )
+        }
+
+        Ok(mods)
+    }
+
+    fn collect_modpacks(
+        &self,
+        name: &str,
+        loaded: &mut Vec<String>,
+        mods: &mut Vec<Mod>,
+    ) -> Result<()> {
+        if loaded.iter().any(|n| n == name) {
+            return Ok(());
+        }
+        loaded.push(name.to_owned());
+
+        let mut modpack = self.get_modpack(name)?;
+        mods.append(&mut modpack.mods);
+
+        for name in modpack.inherit {
+            self.collect_modpacks(&name, loaded, mods)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the path of a server config relative to the server path without the `.cfg`
+    /// extension.
+    ///
+    /// The path can be changed with `config.<name>.path` and defaults to the name.
+    pub fn get_server_config(&self, name: &str) -> Result<String> {
+        Ok(self
+            .get_str(&format!("config.{}.path", name))
+            .context("Could not get config file path from config")?
+            .unwrap_or_else(|| name.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn settings(content: &str) -> (tempfile::TempDir, Settings) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("amraam.toml");
+        fs::write(&path, content).unwrap();
+
+        let settings = Settings::from_path(Some(&path)).unwrap();
+        (dir, settings)
+    }
+
+    #[test]
+    fn test_get_instance() {
+        let (_dir, settings) = settings(
+            r#"
+[options.global]
+config = "global"
+port = 2502
+
+[options.main]
+config = "main"
+modpack = "main"
+
+[options.training]
+config = "training"
+
+[instance.public]
+option_set = "main"
+port = 2402
+
+[instance.event]
+option_set = "main"
+modpack = "event"
+profiles = "event"
+binary = "arma3server"
+"#,
+        );
+
+        let public = settings.get_instance(Some("public"), None).unwrap();
+        assert_eq!(public.name, "public");
+        assert_eq!(public.options.port, Some(2402));
+        assert_eq!(public.options.config.as_deref(), Some("main"));
+        assert_eq!(public.options.profiles.as_deref(), Some("profiles/public"));
+        assert_eq!(public.sources["port"], "instance.public");
+        assert_eq!(public.sources["config"], "options.main");
+        assert_eq!(public.sources["profiles"], "default");
+        assert_eq!(
+            public.options.binary.as_deref(),
+            Some("arma3serverprofiling_x64")
+        );
+        assert_eq!(public.sources["binary"], "server.branch");
+
+        let event = settings.get_instance(Some("event"), None).unwrap();
+        assert_eq!(event.options.modpack.as_deref(), Some("event"));
+        assert_eq!(event.options.profiles.as_deref(), Some("event"));
+        assert_eq!(event.options.binary.as_deref(), Some("arma3server"));
+
+        // option sets can be used like instances
+        let training = settings.get_instance(Some("training"), None).unwrap();
+        assert_eq!(training.name, "training");
+        assert_eq!(training.options.config.as_deref(), Some("training"));
+
+        // the option set takes precedence over the global options
+        let main = settings.get_instance(None, Some("main")).unwrap();
+        assert_eq!(main.options.config.as_deref(), Some("main"));
+        assert_eq!(main.options.port, Some(2502));
+        assert_eq!(main.sources["port"], "options.global");
+        assert_eq!(main.sources["bandwidth_algorithm"], "default");
+
+        let overridden = settings
+            .get_instance_with(
+                Some("public"),
+                None,
+                OptionSet {
+                    port: Some(2602),
+                    ..OptionSet::new()
+                },
+            )
+            .unwrap();
+        assert_eq!(overridden.options.port, Some(2602));
+        assert_eq!(overridden.sources["port"], "command line");
+
+        let global = settings.get_instance(None, None).unwrap();
+        assert_eq!(global.name, DEFAULT_NAME);
+        assert_eq!(global.options.config.as_deref(), Some("global"));
+
+        assert!(settings.get_instance(Some("missing"), None).is_err());
+        assert_eq!(
+            settings.get_instance_names().unwrap(),
+            vec!["event", "public"]
+        );
+    }
+
+    #[test]
+    fn test_get_modpack_mods() {
+        let (dir, settings) = settings(
+            r#"
+[modpack.top]
+mods = ["tfar"]
+inherit = ["mid"]
+
+[modpack.mid]
+mods = ["ace"]
+inherit = ["base", "top"]
+
+[modpack.base]
+mods = ["cba"]
+"#,
+        );
+        let server_path = dir.path().to_str().unwrap();
+
+        for name in &["cba", "ace", "tfar"] {
+            fs::create_dir_all(dir.path().join("mods").join(name)).unwrap();
+        }
+
+        let mods = settings.get_modpack_mods("top", server_path).unwrap();
+        assert_eq!(
+            mods.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(),
+            vec!["tfar", "ace", "cba"]
+        );
+
+        fs::remove_dir(dir.path().join("mods/cba")).unwrap();
+        assert!(settings.get_modpack_mods("top", server_path).is_err());
+    }
+
+    #[test]
+    fn test_binary() {
+        let (_dir, stable) = settings("[server]\nbranch = \"stable\"\nbits = 32\n");
+        let instance = stable.get_instance(None, None).unwrap();
+        assert_eq!(instance.options.binary.as_deref(), Some("arma3server"));
+
+        let (_dir, invalid) = settings("[server]\nbits = 16\n");
+        assert!(invalid.get_instance(None, None).is_err());
+    }
 }
@@ -1,9 +1,64 @@
 use anyhow::{bail, ensure, Context, Result};
-use std::process::Command;
+use std::{fmt, process::Command};
 
 static ARMA_SERVER_APPID: &str = "233780";
 static ARMA_APPID: &str = "107410";
 
+/// A branch of the ArmA 3 Server, set with `server.branch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Branch {
+    Stable,
+    // installations before `server.branch` were on the profiling branch
+    #[default]
+    Profiling,
+    Contact,
+    CreatorDlc,
+}
+
+impl Branch {
+    pub const ALL: &'static [Branch] = &[
+        Branch::Stable,
+        Branch::Profiling,
+        Branch::Contact,
+        Branch::CreatorDlc,
+    ];
+
+    /// The name of the beta and its password.
+    fn beta(self) -> (&'static str, Option<&'static str>) {
+        match self {
+            Branch::Stable => ("public", None),
+            Branch::Profiling => (
+                "profiling",
+                Some("CautionSpecialProfilingAndTestingBranchArma3"),
+            ),
+            Branch::Contact => ("contact", None),
+            Branch::CreatorDlc => ("creatordlc", None),
+        }
+    }
+
+    /// The server binary of the branch, the 32-bit one unless `x64` is set.
+    pub fn default_binary(self, x64: bool) -> &'static str {
+        match (self, x64) {
+            (Branch::Profiling, true) => "arma3serverprofiling_x64",
+            (Branch::Profiling, false) => "arma3serverprofiling",
+            (_, true) => "arma3server_x64",
+            (_, false) => "arma3server",
+        }
+    }
+}
+
+impl fmt::Display for Branch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Branch::Stable => write!(f, "stable"),
+            Branch::Profiling => write!(f, "profiling"),
+            Branch::Contact => write!(f, "contact"),
+            Branch::CreatorDlc => write!(f, "creatordlc"),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct SteamCmd<'a> {
     sudo: &'a str,
@@ -61,16 +116,15 @@ impl<'a> SteamCmd<'a> {
         self.run(&[&["+workshop_download_item", ARMA_APPID], mod_ids].concat())
     }
 
-    pub fn update_arma(&self) -> Result<()> {
-        self.run(&[
-            "+app_update",
-            ARMA_SERVER_APPID,
-            "-validate",
-            "-beta",
-            "profiling",
-            "-betapassword",
-            "CautionSpecialProfilingAndTestingBranchArma3",
-        ])
+    pub fn update_arma(&self, branch: Branch) -> Result<()> {
+        let (beta, password) = branch.beta();
+
+        let mut args = vec!["+app_update", ARMA_SERVER_APPID, "-validate", "-beta", beta];
+        if let Some(password) = password {
+            args.extend(&["-betapassword", password]);
+        }
+
+        self.run(&args)
     }
 
     fn binary_path<'p>() -> Result<&'p str> {
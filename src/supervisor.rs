@@ -0,0 +1,739 @@
+//! Restart the server when it crashes.
+//!
+//! A server exiting with a non-zero exit code or killed by a signal is considered crashed and
+//! restarted after a delay that doubles with every crash in a row. A server that exits
+//! successfully, e.g. after `#shutdown`, or receives SIGINT/SIGTERM via the supervisor is not
+//! restarted.
+//!
+//! Additionally the server can be restarted at fixed times of the day. Players are warned via
+//! RCon before the server is shut down.
+
+use crate::{ports, rcon::RCon, settings::Instance, Settings};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use config::Value;
+use console::{style, Term};
+use nix::{
+    libc,
+    sys::signal::{self, kill, SigHandler, Signal},
+    unistd::Pid,
+};
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    process::{Child, Command, ExitStatus},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the server process is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the server gets to shut down before it is terminated.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long the server gets to exit after SIGTERM before it is killed.
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(60);
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_stop(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Handle SIGINT and SIGTERM by requesting a stop instead of exiting, so the server can be
+/// terminated first.
+pub fn handle_stop_signals() -> Result<()> {
+    let handler = SigHandler::Handler(request_stop);
+    unsafe {
+        signal::signal(Signal::SIGINT, handler).context("Could not handle SIGINT")?;
+        signal::signal(Signal::SIGTERM, handler).context("Could not handle SIGTERM")?;
+    }
+
+    Ok(())
+}
+
+/// Wait for the server to exit, terminating it when a stop is requested.
+///
+/// Returns `None` if the server was stopped. [`handle_stop_signals`] has to be called first.
+pub fn wait(child: &mut Child) -> Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait().context("Could not wait for server")? {
+            return Ok(Some(status));
+        }
+
+        if STOP.load(Ordering::SeqCst) {
+            log("Stop requested, terminating server");
+            terminate(child)?;
+            return Ok(None);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Get the key `instance.<name>.<key>` if the instance sets it, otherwise `global`.
+///
+/// Instances of the same installation are supervised separately, so they can override the
+/// global supervisor and RCon settings.
+fn instance_key(
+    settings: &Settings,
+    instance: &Instance,
+    key: &str,
+    global: &str,
+) -> Result<String> {
+    let key = format!("instance.{}.{}", instance.name, key);
+
+    let set = settings
+        .get::<Value>(&key)
+        .with_context(|| format!("Could not get {} from config", key))?
+        .is_some();
+
+    Ok(if set { key } else { global.to_owned() })
+}
+
+/// When and how often a crashed server is restarted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestartPolicy {
+    /// The delay before the first restart.
+    pub delay: Duration,
+    /// The upper bound of the doubling delay.
+    pub max_delay: Duration,
+    /// The number of restarts allowed within `window`.
+    pub max_restarts: usize,
+    /// A server running longer than this is considered stable again and resets the delay.
+    pub window: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(300),
+            max_restarts: 5,
+            window: Duration::from_secs(600),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Read the policy of `instance` from its table or the `supervisor` table. All durations are
+    /// in seconds.
+    pub fn from_settings(settings: &Settings, instance: &Instance) -> Result<Self> {
+        let default = Self::default();
+        let int = |key: &str| -> Result<Option<i64>> {
+            let key = instance_key(settings, instance, key, &format!("supervisor.{}", key))?;
+            settings
+                .get_int(&key)
+                .with_context(|| format!("Could not get {} from config", key))
+        };
+        let seconds = |key: &str, default: Duration| -> Result<Duration> {
+            Ok(int(key)?
+                .map(|secs| Duration::from_secs(secs.max(0) as u64))
+                .unwrap_or(default))
+        };
+
+        Ok(Self {
+            delay: seconds("restart_delay", default.delay)?,
+            max_delay: seconds("max_restart_delay", default.max_delay)?,
+            max_restarts: int("max_restarts")?
+                .map(|n| n.max(0) as usize)
+                .unwrap_or(default.max_restarts),
+            window: seconds("restart_window", default.window)?,
+        })
+    }
+}
+
+/// How to reach the BattlEye RCon of the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RConConfig {
+    pub address: String,
+    pub password: String,
+}
+
+impl RConConfig {
+    /// Read the RCon settings of `instance` from `rcon_password`, `rcon_host` and `rcon_port` in
+    /// its table or the `rcon` table. RCon is only used if a password is set.
+    ///
+    /// The port defaults to the BattlEye port of the instance, the last of its ports.
+    pub fn from_settings(settings: &Settings, instance: &Instance) -> Result<Option<Self>> {
+        let str = |key: &str| -> Result<Option<String>> {
+            let key = instance_key(
+                settings,
+                instance,
+                &format!("rcon_{}", key),
+                &format!("rcon.{}", key),
+            )?;
+            settings
+                .get_str(&key)
+                .with_context(|| format!("Could not get {} from config", key))
+        };
+
+        let password = match str("password")? {
+            Some(password) => password,
+            None => return Ok(None),
+        };
+
+        let host = str("host")?.unwrap_or_else(|| "127.0.0.1".to_owned());
+        let port = match str("port")? {
+            Some(port) => port.parse().context("RCon port is not a number")?,
+            None => *ports::ports(instance.options.port.unwrap_or(2302))?.end(),
+        };
+
+        Ok(Some(Self {
+            address: format!("{}:{}", host, port),
+            password,
+        }))
+    }
+
+    pub fn connect(&self) -> Result<RCon> {
+        RCon::connect(self.address.as_str(), &self.password)
+    }
+}
+
+/// Restarts at fixed times of the day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub times: Vec<NaiveTime>,
+    /// How long before a restart players are warned.
+    pub warnings: Vec<Duration>,
+    pub rcon: Option<RConConfig>,
+    /// A shell command run while the server is stopped, e.g. to update mods.
+    pub command: Option<String>,
+}
+
+impl Schedule {
+    /// Read `restarts`, `restart_warnings` (in minutes) and `restart_command` of `instance` from
+    /// its table or the `supervisor` table. Returns `None` if no restarts are configured.
+    pub fn from_settings(settings: &Settings, instance: &Instance) -> Result<Option<Self>> {
+        let key = |key: &str| instance_key(settings, instance, key, &format!("supervisor.{}", key));
+
+        let restarts = key("restarts")?;
+        let times = settings
+            .get::<Vec<String>>(&restarts)
+            .with_context(|| format!("Could not get {} from config", restarts))?
+            .unwrap_or_default()
+            .iter()
+            .map(|time| {
+                NaiveTime::parse_from_str(time, "%H:%M")
+                    .with_context(|| format!("Invalid restart time {}, expected HH:MM", time))
+            })
+            .collect::<Result<Vec<NaiveTime>>>()?;
+
+        if times.is_empty() {
+            return Ok(None);
+        }
+
+        let restart_warnings = key("restart_warnings")?;
+        let mut warnings = settings
+            .get::<Vec<u64>>(&restart_warnings)
+            .with_context(|| format!("Could not get {} from config", restart_warnings))?
+            .unwrap_or_else(|| vec![15, 5, 1])
+            .into_iter()
+            .map(|minutes| Duration::from_secs(minutes * 60))
+            .collect::<Vec<Duration>>();
+        warnings.sort_by(|a, b| b.cmp(a));
+
+        let restart_command = key("restart_command")?;
+
+        Ok(Some(Self {
+            times,
+            warnings,
+            rcon: RConConfig::from_settings(settings, instance)?,
+            command: settings
+                .get_str(&restart_command)
+                .with_context(|| format!("Could not get {} from config", restart_command))?,
+        }))
+    }
+
+    /// Get the first restart after `now`.
+    pub fn next_restart<Tz>(&self, now: DateTime<Tz>) -> Option<DateTime<Tz>>
+    where
+        Tz: TimeZone,
+    {
+        let today = now.date_naive();
+        let tomorrow = today.succ_opt()?;
+
+        [today, tomorrow]
+            .iter()
+            .flat_map(|date| self.times.iter().map(move |&time| date.and_time(time)))
+            .filter_map(|restart| now.timezone().from_local_datetime(&restart).earliest())
+            .filter(|restart| *restart > now)
+            .min()
+    }
+}
+
+/// Why the server process ended.
+enum Exit {
+    Exited(ExitStatus),
+    /// A stop was requested via SIGINT or SIGTERM.
+    Stopped,
+    /// The server was shut down for a scheduled restart.
+    Restart,
+}
+
+/// Keeps track of crashes to decide when to restart the server.
+#[derive(Debug)]
+pub struct Supervisor {
+    policy: RestartPolicy,
+    schedule: Option<Schedule>,
+    delay: Duration,
+    restarts: VecDeque<Instant>,
+}
+
+impl Supervisor {
+    pub fn new(policy: RestartPolicy, schedule: Option<Schedule>) -> Self {
+        Self {
+            delay: policy.delay,
+            policy,
+            schedule,
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// Record a crash of a server that ran for `uptime`.
+    ///
+    /// Returns the delay before the next restart or `None` if the server crashed too often.
+    pub fn on_crash(&mut self, now: Instant, uptime: Duration) -> Option<Duration> {
+        if uptime >= self.policy.window {
+            self.delay = self.policy.delay;
+        }
+
+        while let Some(&restart) = self.restarts.front() {
+            if now.duration_since(restart) < self.policy.window {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+
+        if self.restarts.len() >= self.policy.max_restarts {
+            return None;
+        }
+
+        self.restarts.push_back(now);
+
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.policy.max_delay);
+
+        Some(delay)
+    }
+
+    /// Start the server with `spawn` and restart it whenever it crashes.
+    ///
+    /// Returns when the server stops on purpose, SIGINT or SIGTERM is received or the server
+    /// crashed too often.
+    pub fn run<F>(&mut self, mut spawn: F) -> Result<()>
+    where
+        F: FnMut() -> Result<Child>,
+    {
+        handle_stop_signals()?;
+
+        loop {
+            let started = Instant::now();
+            let mut child = spawn().context("Could not start server")?;
+            log(&format!("Started server with pid {}", child.id()));
+
+            let restart_at = self
+                .schedule
+                .as_ref()
+                .and_then(|schedule| schedule.next_restart(Local::now()));
+            if let Some(restart_at) = restart_at {
+                log(&format!(
+                    "Next restart at {}",
+                    restart_at.format("%Y-%m-%d %H:%M")
+                ));
+            }
+
+            let status = match self.wait(&mut child, restart_at)? {
+                Exit::Exited(status) => status,
+                Exit::Stopped => {
+                    log("Stopped server");
+                    return Ok(());
+                }
+                Exit::Restart => {
+                    self.run_restart_command();
+                    continue;
+                }
+            };
+
+            if status.success() {
+                log("Server stopped");
+                return Ok(());
+            }
+
+            let reason = describe(status);
+
+            let delay = match self.on_crash(Instant::now(), started.elapsed()) {
+                Some(delay) => delay,
+                None => bail!(
+                    "Server {} and crashed more than {} times within {} seconds, giving up",
+                    reason,
+                    self.policy.max_restarts,
+                    self.policy.window.as_secs()
+                ),
+            };
+
+            log(&format!(
+                "Server {}, restarting in {} seconds",
+                style(&reason).red(),
+                delay.as_secs()
+            ));
+
+            let until = Instant::now() + delay;
+            while Instant::now() < until {
+                if STOP.load(Ordering::SeqCst) {
+                    log("Not restarting server since a stop was requested");
+                    return Ok(());
+                }
+                thread::sleep(POLL_INTERVAL.min(until - Instant::now()));
+            }
+        }
+    }
+
+    /// Wait for the server to exit, warning players and shutting it down at `restart_at`.
+    fn wait(&self, child: &mut Child, restart_at: Option<DateTime<Local>>) -> Result<Exit> {
+        let remaining = || restart_at.map(|at| (at - Local::now()).to_std().unwrap_or_default());
+
+        // warnings for a restart that is closer than the warning when the server starts are
+        // skipped
+        let mut warnings = match (&self.schedule, remaining()) {
+            (Some(schedule), Some(remaining)) => schedule
+                .warnings
+                .iter()
+                .copied()
+                .filter(|&warning| warning < remaining)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        loop {
+            if let Some(status) = child.try_wait().context("Could not wait for server")? {
+                return Ok(Exit::Exited(status));
+            }
+
+            if STOP.load(Ordering::SeqCst) {
+                log("Stop requested, terminating server");
+                terminate(child)?;
+                return Ok(Exit::Stopped);
+            }
+
+            if let Some(remaining) = remaining() {
+                if warnings.first().map(|&w| remaining <= w).unwrap_or(false) {
+                    let warning = warnings.remove(0);
+                    self.warn(warning);
+                }
+
+                if remaining == Duration::from_secs(0) {
+                    log("Shutting down server for scheduled restart");
+                    self.shutdown(child)?;
+                    return Ok(Exit::Restart);
+                }
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn rcon(&self) -> Option<&RConConfig> {
+        self.schedule.as_ref().and_then(|s| s.rcon.as_ref())
+    }
+
+    /// Tell the players about an upcoming restart.
+    fn warn(&self, remaining: Duration) {
+        let minutes = remaining.as_secs().div_ceil(60);
+        let message = format!(
+            "Server restarts in {} minute{}",
+            minutes,
+            if minutes == 1 { "" } else { "s" }
+        );
+        log(&message);
+
+        if let Some(rcon) = self.rcon() {
+            if let Err(err) = rcon.connect().and_then(|mut rcon| rcon.say(&message)) {
+                log(&format!("Could not warn players via RCon: {:#}", err));
+            }
+        }
+    }
+
+    /// Shut down the server via RCon, terminating it if that fails or takes too long.
+    fn shutdown(&self, child: &mut Child) -> Result<()> {
+        if let Some(rcon) = self.rcon() {
+            match rcon
+                .connect()
+                .and_then(|mut rcon| rcon.command("#shutdown"))
+            {
+                Ok(_) => {
+                    let until = Instant::now() + SHUTDOWN_TIMEOUT;
+                    while Instant::now() < until {
+                        if child
+                            .try_wait()
+                            .context("Could not wait for server")?
+                            .is_some()
+                        {
+                            return Ok(());
+                        }
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                    log("Server did not shut down in time, terminating it");
+                }
+                Err(err) => log(&format!("Could not shut down server via RCon: {:#}", err)),
+            }
+        }
+
+        terminate(child)
+    }
+
+    fn run_restart_command(&self) {
+        let command = match self.schedule.as_ref().and_then(|s| s.command.as_ref()) {
+            Some(command) => command,
+            None => return,
+        };
+
+        log(&format!("Running restart command `{}`", command));
+
+        match Command::new("sh").args(["-c", command]).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => log(&format!("Restart command {}", describe(status))),
+            Err(err) => log(&format!("Could not run restart command: {}", err)),
+        }
+    }
+}
+
+/// Send SIGTERM to the server and SIGKILL if it did not exit within [`TERMINATE_TIMEOUT`].
+fn terminate(child: &mut Child) -> Result<()> {
+    terminate_within(child, TERMINATE_TIMEOUT)
+}
+
+fn terminate_within(child: &mut Child, timeout: Duration) -> Result<()> {
+    let pid = Pid::from_raw(child.id() as i32);
+    kill(pid, Signal::SIGTERM).context("Could not terminate server")?;
+
+    let until = Instant::now() + timeout;
+    while Instant::now() < until {
+        if child
+            .try_wait()
+            .context("Could not wait for server")?
+            .is_some()
+        {
+            return Ok(());
+        }
+        thread::sleep(POLL_INTERVAL.min(until - Instant::now()));
+    }
+
+    log("Server did not exit in time, killing it");
+    child.kill().context("Could not kill server")?;
+    child.wait().context("Could not wait for server")?;
+
+    Ok(())
+}
+
+fn describe(status: ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exited with code {}", code),
+        (None, Some(signal)) => match Signal::try_from(signal) {
+            Ok(signal) => format!("was killed by {}", signal.as_str()),
+            Err(_) => format!("was killed by signal {}", signal),
+        },
+        (None, None) => "exited".to_owned(),
+    }
+}
+
+fn log(message: &str) {
+    let line = format!("[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), message);
+    if Term::stdout().write_line(&line).is_err() {
+        info!("{}", message);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy {
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(3),
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut supervisor = Supervisor::new(policy(), None);
+        let start = Instant::now();
+        let uptime = Duration::from_secs(1);
+
+        assert_eq!(
+            supervisor.on_crash(start, uptime),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            supervisor.on_crash(start + Duration::from_secs(2), uptime),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            supervisor.on_crash(start + Duration::from_secs(5), uptime),
+            Some(Duration::from_secs(3))
+        );
+        // too many restarts within the window
+        assert_eq!(
+            supervisor.on_crash(start + Duration::from_secs(9), uptime),
+            None
+        );
+        // the first restarts left the window and the server ran long enough to reset the delay
+        assert_eq!(
+            supervisor.on_crash(start + Duration::from_secs(70), Duration::from_secs(61)),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let mut supervisor = Supervisor::new(
+            RestartPolicy {
+                delay: Duration::from_millis(0),
+                ..policy()
+            },
+            None,
+        );
+
+        let mut runs = 0;
+        supervisor
+            .run(|| {
+                runs += 1;
+                let code = if runs < 3 { "exit 1" } else { "exit 0" };
+                Ok(Command::new("sh").args(["-c", code]).spawn()?)
+            })
+            .unwrap();
+        assert_eq!(runs, 3);
+
+        assert!(supervisor
+            .run(|| Ok(Command::new("sh").args(["-c", "exit 1"]).spawn()?))
+            .is_err());
+    }
+
+    #[test]
+    fn test_terminate_kills() {
+        use std::os::unix::process::ExitStatusExt;
+
+        // ignored signals stay ignored across exec
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; exec sleep 10"])
+            .spawn()
+            .unwrap();
+        // give the shell time to install the trap
+        thread::sleep(Duration::from_millis(200));
+
+        let started = Instant::now();
+        terminate_within(&mut child, Duration::from_millis(300)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            child.try_wait().unwrap().and_then(|s| s.signal()),
+            Some(Signal::SIGKILL as i32)
+        );
+    }
+
+    #[test]
+    fn test_instance_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("amraam.toml");
+        std::fs::write(
+            &path,
+            r#"
+[rcon]
+password = "secret"
+
+[supervisor]
+restarts = ["04:00"]
+max_restarts = 3
+
+[instance.public]
+port = 2402
+
+[instance.event]
+port = 2502
+rcon_port = 2600
+restarts = ["18:00", "22:00"]
+max_restarts = 10
+"#,
+        )
+        .unwrap();
+        let settings = Settings::from_path(Some(&path)).unwrap();
+
+        let public = settings.get_instance(Some("public"), None).unwrap();
+        let schedule = Schedule::from_settings(&settings, &public)
+            .unwrap()
+            .unwrap();
+        assert_eq!(schedule.times.len(), 1);
+        assert_eq!(schedule.rcon.unwrap().address, "127.0.0.1:2406");
+        assert_eq!(
+            RestartPolicy::from_settings(&settings, &public)
+                .unwrap()
+                .max_restarts,
+            3
+        );
+
+        let event = settings.get_instance(Some("event"), None).unwrap();
+        let schedule = Schedule::from_settings(&settings, &event).unwrap().unwrap();
+        assert_eq!(schedule.times.len(), 2);
+        assert_eq!(schedule.rcon.unwrap().address, "127.0.0.1:2600");
+        assert_eq!(
+            RestartPolicy::from_settings(&settings, &event)
+                .unwrap()
+                .max_restarts,
+            10
+        );
+    }
+
+    #[test]
+    fn test_next_restart() {
+        let schedule = Schedule {
+            times: vec![
+                NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(4, 0, 0).unwrap(),
+            ],
+            warnings: Vec::new(),
+            rcon: None,
+            command: None,
+        };
+        let at = |day, hour, minute| Utc.with_ymd_and_hms(2020, 6, day, hour, minute, 0).unwrap();
+
+        assert_eq!(schedule.next_restart(at(1, 3, 0)), Some(at(1, 4, 0)));
+        assert_eq!(schedule.next_restart(at(1, 4, 0)), Some(at(1, 16, 0)));
+        assert_eq!(schedule.next_restart(at(1, 20, 30)), Some(at(2, 4, 0)));
+    }
+
+    #[test]
+    fn test_scheduled_restart() {
+        let mut supervisor = Supervisor::new(
+            policy(),
+            Some(Schedule {
+                times: Vec::new(),
+                warnings: vec![Duration::from_secs(60)],
+                rcon: None,
+                command: None,
+            }),
+        );
+
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let restart_at = Local::now() + chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            supervisor.wait(&mut child, Some(restart_at)).unwrap(),
+            Exit::Restart
+        ));
+        assert!(child.try_wait().unwrap().is_some());
+
+        supervisor.schedule = None;
+        let mut child = Command::new("sh").args(["-c", "exit 0"]).spawn().unwrap();
+        assert!(matches!(
+            supervisor.wait(&mut child, None).unwrap(),
+            Exit::Exited(status) if status.success()
+        ));
+    }
+}
@@ -1,11 +1,22 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
 use console::style;
 use indicatif::ProgressBar;
-use nix::unistd::{self, User};
+use nix::{
+    libc,
+    sys::{
+        stat,
+        time::{TimeVal, TimeValLike},
+    },
+    unistd::{self, User},
+};
 use std::{
+    collections::BTreeMap,
+    ffi::CString,
     fs::{self, set_permissions, Permissions},
-    os::unix::fs::PermissionsExt,
-    path::Path,
+    io,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
 
@@ -102,44 +113,298 @@ where
     Ok(())
 }
 
-pub fn lowercase<P>(path: P, recursive: bool) -> Result<()>
+/// Remove the file or directory at `path` if it exists.
+pub fn remove_path<P>(path: P) -> io::Result<()>
 where
     P: AsRef<Path>,
 {
     let path = path.as_ref();
 
-    if recursive {
-        let bar = ProgressBar::new_spinner();
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else if path.exists() {
+        fs::remove_file(path)
+    } else {
+        Ok(())
+    }
+}
 
-        let iter = WalkDir::new(&path).follow_links(true);
+/// Replace the file or directory `target` with `new`, keeping the old `target` as `previous`.
+///
+/// An existing `previous` is removed first. `new` and `target` are swapped atomically where the
+/// filesystem supports it. Otherwise `target` is moved aside first and is missing for the time
+/// between two renames; it is restored if `new` cannot be moved into place and a crash in
+/// between leaves only `previous`, which `mods rollback` moves back. The modification time of
+/// `previous` is set to the time it was replaced.
+pub fn replace_path<P, Q, R>(new: P, target: Q, previous: R) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: AsRef<Path>,
+{
+    let (new, target, previous) = (new.as_ref(), target.as_ref(), previous.as_ref());
 
-        for entry in bar.wrap_iter(iter.into_iter()) {
-            let path = entry.context("Could not get path")?.into_path();
-            let new_path = path
-                .to_str()
-                .context("Path is not valid UTF-8")?
-                .to_lowercase();
+    if previous.exists() {
+        debug!("Removing {}", previous.display());
+        remove_path(previous).context("Could not remove previous version")?;
+    }
 
-            debug!("Renaming {} to {}", path.display(), new_path);
-            bar.set_message(&format!("{}", path.display()));
+    if !target.exists() {
+        debug!("Renaming {} to {}", new.display(), target.display());
+        return fs::rename(new, target).context("Could not move new version into place");
+    }
+
+    debug!("Exchanging {} and {}", new.display(), target.display());
 
-            fs::rename(path, new_path).context("Could not rename path")?;
+    match exchange(new, target) {
+        Ok(()) => {
+            fs::rename(new, previous).context("Could not keep previous version")?;
         }
+        Err(err) => {
+            debug!("Could not exchange atomically, renaming instead: {}", err);
 
-        bar.finish_with_message(&format!(
-            "{} renaming files",
-            style("Finished").green().bold()
-        ));
+            fs::rename(target, previous).context("Could not move current version aside")?;
+
+            if let Err(err) = fs::rename(new, target) {
+                fs::rename(previous, target).context("Could not restore current version")?;
+                return Err(err).context("Could not move new version into place");
+            }
+        }
+    }
+
+    let now = TimeVal::seconds(Utc::now().timestamp());
+    stat::utimes(previous, &now, &now).context("Could not set modification time")?;
+
+    Ok(())
+}
+
+/// Atomically swap the paths `a` and `b`, which both have to exist.
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
     } else {
-        let new_path = path
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// A single rename performed (or planned) by [`lowercase`].
+///
+/// Both paths are relative to the directory passed to [`lowercase`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Rename every file and directory below `path` to lowercase.
+///
+/// Only the path relative to `path` is changed, `path` itself and its parents are left alone.
+/// Entries are renamed bottom-up so a directory is only renamed after its contents. Before
+/// anything is touched all entries are checked for case collisions (e.g. `Foo.pbo` and
+/// `foo.pbo` in the same directory) and the whole operation is aborted if one is found.
+///
+/// With `dry_run` set nothing is renamed. Either way the list of renames is returned.
+pub fn lowercase<P>(path: P, dry_run: bool) -> Result<Vec<Rename>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut names: BTreeMap<(PathBuf, String), Vec<PathBuf>> = BTreeMap::new();
+    let mut renames = Vec::new();
+
+    let iter = WalkDir::new(path)
+        .min_depth(1)
+        .contents_first(true)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()));
+
+    for entry in iter {
+        let entry = entry.context("Could not get path")?;
+        let relative = entry
+            .path()
+            .strip_prefix(path)
+            .context("Path is not inside the root directory")?
+            .to_owned();
+        let name = entry
+            .file_name()
             .to_str()
-            .context("Path is not valid UTF-8")?
-            .to_lowercase();
+            .with_context(|| format!("Path is not valid UTF-8: {}", relative.display()))?;
+        let parent = relative.parent().map(Path::to_owned).unwrap_or_default();
+        let lower = name.to_lowercase();
 
-        debug!("Renaming {} to {}", path.display(), new_path);
+        names
+            .entry((parent.clone(), lower.clone()))
+            .or_default()
+            .push(relative.clone());
 
-        fs::rename(path, new_path).context("Could not rename path")?;
+        if name != lower {
+            renames.push(Rename {
+                to: parent.join(lower),
+                from: relative,
+            });
+        }
     }
 
-    Ok(())
+    let collisions = names
+        .values()
+        .filter(|paths| paths.len() > 1)
+        .map(|paths| {
+            paths
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .collect::<Vec<_>>();
+
+    if !collisions.is_empty() {
+        bail!(
+            "Cannot rename files to lowercase since some names would collide:\n{}",
+            collisions.join("\n")
+        );
+    }
+
+    if dry_run {
+        return Ok(renames);
+    }
+
+    let bar = ProgressBar::new(renames.len() as u64);
+
+    // Renames were collected contents first so every parent still has its original name here.
+    for rename in bar.wrap_iter(renames.iter()) {
+        debug!(
+            "Renaming {} to {}",
+            rename.from.display(),
+            rename.to.display()
+        );
+        bar.set_message(&format!("{}", rename.from.display()));
+
+        fs::rename(path.join(&rename.from), path.join(&rename.to))
+            .with_context(|| format!("Could not rename {}", rename.from.display()))?;
+    }
+
+    bar.finish_with_message(&format!(
+        "{} renaming files",
+        style("Finished").green().bold()
+    ));
+
+    Ok(renames)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, File};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_replace_path() {
+        let dir = tempdir().unwrap();
+        let (new, target, previous) = (
+            dir.path().join("ace.new"),
+            dir.path().join("ace"),
+            dir.path().join("ace.prev"),
+        );
+        create_dir_all(&new).unwrap();
+        create_dir_all(&target).unwrap();
+        create_dir_all(&previous).unwrap();
+        File::create(new.join("new")).unwrap();
+        File::create(target.join("old")).unwrap();
+
+        replace_path(&new, &target, &previous).unwrap();
+
+        assert!(!new.exists());
+        assert!(target.join("new").is_file());
+        assert!(previous.join("old").is_file());
+    }
+
+    #[test]
+    fn test_replace_path_without_target() {
+        let dir = tempdir().unwrap();
+        let (new, target) = (dir.path().join("ace.new"), dir.path().join("ace"));
+        create_dir_all(&new).unwrap();
+
+        replace_path(&new, &target, dir.path().join("ace.prev")).unwrap();
+
+        assert!(!new.exists());
+        assert!(target.is_dir());
+        assert!(!dir.path().join("ace.prev").exists());
+    }
+
+    #[test]
+    fn test_replace_path_restores_target() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("ace");
+        create_dir_all(&target).unwrap();
+
+        assert!(replace_path(
+            dir.path().join("missing"),
+            &target,
+            dir.path().join("ace.prev")
+        )
+        .is_err());
+        assert!(target.is_dir());
+    }
+
+    #[test]
+    fn test_lowercase() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("@Mod");
+        create_dir_all(root.join("Addons")).unwrap();
+        File::create(root.join("Addons/Foo.PBO")).unwrap();
+        File::create(root.join("mod.cpp")).unwrap();
+
+        let renames = lowercase(&root, false).unwrap();
+
+        assert_eq!(
+            renames,
+            vec![
+                Rename {
+                    from: "Addons/Foo.PBO".into(),
+                    to: "Addons/foo.pbo".into(),
+                },
+                Rename {
+                    from: "Addons".into(),
+                    to: "addons".into(),
+                },
+            ]
+        );
+        assert!(root.join("addons/foo.pbo").is_file());
+        assert!(root.join("mod.cpp").is_file());
+        // the root directory itself is never renamed
+        assert!(root.is_dir());
+    }
+
+    #[test]
+    fn test_lowercase_dry_run() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("Foo.pbo")).unwrap();
+
+        let renames = lowercase(dir.path(), true).unwrap();
+
+        assert_eq!(renames.len(), 1);
+        assert!(dir.path().join("Foo.pbo").is_file());
+    }
+
+    #[test]
+    fn test_lowercase_collision() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("Foo.pbo")).unwrap();
+        File::create(dir.path().join("foo.pbo")).unwrap();
+
+        assert!(lowercase(dir.path(), false).is_err());
+        assert!(dir.path().join("Foo.pbo").is_file());
+    }
 }
@@ -2,10 +2,20 @@ use anyhow::{Context, Result};
 use nix::unistd::{seteuid, Uid};
 use std::{
     fs::read_dir,
-    path::Path,
+    path::{Path, PathBuf},
     process::{exit, Command},
 };
 
+/// Suffix of the directory a mod update is installed into before it replaces the old version.
+pub static STAGED_SUFFIX: &str = ".new";
+
+/// Suffix of the directory the previous version of an updated mod is kept in.
+pub static PREVIOUS_SUFFIX: &str = ".prev";
+
+fn is_mod_name(name: &str) -> bool {
+    !name.starts_with('.') && !name.ends_with(STAGED_SUFFIX) && !name.ends_with(PREVIOUS_SUFFIX)
+}
+
 pub fn list_mods<P>(path: P) -> Result<Vec<String>>
 where
     P: AsRef<Path>,
@@ -13,6 +23,7 @@ where
     let mut mods = read_dir(path)
         .context("Could not read directory contents")?
         .filter_map(|entry| entry.ok())
+        .filter(|entry| is_mod_name(&entry.file_name().to_string_lossy()))
         .map(|entry| {
             Ok(entry
                 .path()
@@ -29,6 +40,28 @@ where
     Ok(mods)
 }
 
+/// List the previous versions of updated mods kept for rollback.
+pub fn list_previous<P>(path: P) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let mut previous = read_dir(path)
+        .context("Could not read directory contents")?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with(PREVIOUS_SUFFIX)
+        })
+        .map(|entry| entry.path())
+        .collect::<Vec<PathBuf>>();
+
+    previous.sort();
+
+    Ok(previous)
+}
+
 /// Escalate with sudo or set uid when not running with root permission.
 ///
 /// This is equal to